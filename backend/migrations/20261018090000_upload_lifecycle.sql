@@ -0,0 +1,10 @@
+ALTER TABLE uploads ADD COLUMN status TEXT NOT NULL DEFAULT 'pending';
+ALTER TABLE uploads ADD CONSTRAINT uploads_status_check CHECK (status IN ('pending', 'completed', 'failed', 'abandoned'));
+ALTER TABLE uploads ADD COLUMN size_bytes BIGINT;
+ALTER TABLE uploads ADD COLUMN etag TEXT;
+ALTER TABLE uploads ADD COLUMN completed_at TIMESTAMPTZ;
+
+-- Uploads registered before the lifecycle existed were listed as if they were complete, keep it that way
+UPDATE uploads SET status = 'completed', completed_at = updated_at;
+
+CREATE INDEX IF NOT EXISTS uploads_user_id_status_idx ON uploads (user_id, status);
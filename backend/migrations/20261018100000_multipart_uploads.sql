@@ -0,0 +1,11 @@
+ALTER TABLE uploads ADD COLUMN multipart_upload_id TEXT;
+
+CREATE TABLE IF NOT EXISTS upload_parts (
+    upload_id UUID NOT NULL REFERENCES uploads(id) ON DELETE CASCADE,
+    part_number INTEGER NOT NULL CHECK (part_number BETWEEN 1 AND 10000),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    etag TEXT NOT NULL,
+    size_bytes BIGINT,
+    PRIMARY KEY (upload_id, part_number)
+);
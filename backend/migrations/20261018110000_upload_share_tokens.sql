@@ -0,0 +1,7 @@
+ALTER TABLE uploads ADD COLUMN share_token TEXT;
+UPDATE uploads SET share_token = replace(gen_random_uuid()::text, '-', '') || replace(gen_random_uuid()::text, '-', '');
+ALTER TABLE uploads ALTER COLUMN share_token SET NOT NULL;
+ALTER TABLE uploads ADD CONSTRAINT uploads_share_token_key UNIQUE (share_token);
+
+-- Download URLs are now presigned on demand through the share link
+ALTER TABLE uploads DROP COLUMN presigned_get;
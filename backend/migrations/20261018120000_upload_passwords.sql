@@ -0,0 +1,3 @@
+ALTER TABLE uploads ADD COLUMN password_hash TEXT;
+ALTER TABLE uploads ADD COLUMN password_failed_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE uploads ADD COLUMN password_locked_until TIMESTAMPTZ;
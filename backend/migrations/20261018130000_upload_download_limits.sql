@@ -0,0 +1,4 @@
+ALTER TABLE uploads ADD COLUMN downloads INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE uploads ADD COLUMN max_downloads INTEGER;
+ALTER TABLE uploads ADD CONSTRAINT uploads_max_downloads_check CHECK (max_downloads > 0);
+ALTER TABLE uploads ADD COLUMN delete_when_exhausted BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+-- Keyset pagination of the uploads of a user walks this index in both directions
+CREATE INDEX IF NOT EXISTS uploads_user_id_created_at_id_idx ON uploads (user_id, created_at, id);
@@ -0,0 +1,15 @@
+-- Addresses that only differ by case have to be merged or deleted by hand first, `fileshare-email-report` lists them
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM users GROUP BY lower(email) HAVING COUNT(*) > 1) THEN
+        RAISE EXCEPTION 'Some users have emails that only differ by case, run fileshare-email-report and resolve them first';
+    END IF;
+END $$;
+
+-- Domains are case-insensitive, local parts are kept as they were typed
+UPDATE users
+SET email = substring(email FROM '^(.*)@') || '@' || lower(substring(email FROM '@([^@]*)$'))
+WHERE email LIKE '%@%';
+
+ALTER TABLE users DROP CONSTRAINT users_email_key;
+CREATE UNIQUE INDEX users_email_lower_key ON users (lower(email));
@@ -0,0 +1,6 @@
+-- Address each verification link was sent to, a verification of another address than the user's one is an email change
+ALTER TABLE verifications ADD COLUMN email TEXT;
+UPDATE verifications SET email = users.email FROM users WHERE verifications.user_id = users.id;
+-- Verifications of deleted users can not be used anymore
+DELETE FROM verifications WHERE email IS NULL;
+ALTER TABLE verifications ALTER COLUMN email SET NOT NULL;
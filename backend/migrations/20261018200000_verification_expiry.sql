@@ -0,0 +1,6 @@
+ALTER TABLE verifications ADD COLUMN expires_at TIMESTAMPTZ;
+UPDATE verifications SET expires_at = created_at + interval '1 day';
+ALTER TABLE verifications ALTER COLUMN expires_at SET NOT NULL;
+
+-- Resend limits count the verifications of a user over the last day
+CREATE INDEX IF NOT EXISTS verifications_user_id_created_at_idx ON verifications (user_id, created_at);
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
+ALTER TABLE users ADD CONSTRAINT users_locale_check CHECK (locale IN ('en', 'fr'));
@@ -0,0 +1,4 @@
+-- Set once the owner was warned that the upload expires soon, so that the warning is only sent once
+ALTER TABLE uploads ADD COLUMN expiry_warned_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS uploads_expires_at_unwarned_idx ON uploads (expires_at) WHERE expiry_warned_at IS NULL;
@@ -0,0 +1,12 @@
+-- Failed logins per account (`email:<address>`) and per client (`ip:<address>`).
+-- Accounts are keyed by address so that unknown emails are throttled like existing ones.
+CREATE TABLE IF NOT EXISTS login_throttles (
+    key TEXT PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    failed_attempts INT NOT NULL DEFAULT 0,
+    last_failed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    locked_until TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS login_throttles_last_failed_at_idx ON login_throttles (last_failed_at);
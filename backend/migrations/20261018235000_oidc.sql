@@ -0,0 +1,28 @@
+-- Accounts created through an identity provider have no password
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+
+-- Accounts of identity providers linked to users, an OIDC account is identified by its issuer and subject
+CREATE TABLE IF NOT EXISTS user_identities (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    -- Email given by the provider on the last login
+    email TEXT,
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_idx ON user_identities (user_id);
+
+-- OIDC logins waiting for the user to come back from the provider, only the hash of the state is stored
+CREATE TABLE IF NOT EXISTS oidc_login_states (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    state_hash TEXT UNIQUE NOT NULL,
+    provider_id TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
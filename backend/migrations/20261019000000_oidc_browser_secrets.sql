@@ -0,0 +1,4 @@
+-- OIDC logins are bound to the browser that started them, through a secret it keeps until the callback.
+-- Logins started before this have no secret and could not be finished anyway.
+DELETE FROM oidc_login_states;
+ALTER TABLE oidc_login_states ADD COLUMN browser_secret_hash TEXT NOT NULL;
@@ -0,0 +1,4 @@
+-- Exhausted uploads are reaped once their last download URL expired, other updates must not delay it.
+-- Until now the last download was the last update of exhausted uploads.
+ALTER TABLE uploads ADD COLUMN last_downloaded_at TIMESTAMPTZ;
+UPDATE uploads SET last_downloaded_at = updated_at WHERE downloads > 0;
@@ -1,7 +1,18 @@
-use fileshare_backend::{app_router, migrate};
-use lambda_http::{Error, run, tracing};
+use axum::{
+    extract::{ConnectInfo, Request},
+    middleware::map_request,
+};
+use fileshare_backend::{
+    app_router, config::Config, migrate, services::ReaperService, state::AppState,
+};
+use lambda_http::{
+    Error, LambdaEvent, lambda_runtime, request::RequestContext, run, service_fn, tracing,
+};
 use sqlx::PgPool;
-use std::env;
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -9,11 +20,45 @@ async fn main() -> Result<(), Error> {
     let _ = dotenvy::dotenv();
     env_logger::init(); // useless for now
 
-    let db_pool = PgPool::connect(&env::var("DATABASE_URL")?)
+    let config = Config::from_env()?;
+    let db_pool = PgPool::connect(&config.database_url)
         .await
         .expect("Connection to database should not fail");
 
-    migrate(&db_pool).await;
-    let app = app_router().with_state(db_pool);
+    migrate(&db_pool, &config.migrations_dir).await;
+    let state = AppState::from_config(db_pool, config)?;
+
+    // The same package is deployed with the "reaper" handler to be invoked on a schedule
+    if env::var("_HANDLER").is_ok_and(|handler| handler == "reaper") {
+        return lambda_runtime::run(service_fn(|_: LambdaEvent<serde_json::Value>| {
+            let state = state.clone();
+            async move {
+                let summary = ReaperService::reap(&state).await.map_err(Error::from)?;
+                println!("Reaper pass done: {summary}");
+                Ok::<String, Error>(summary.to_string())
+            }
+        }))
+        .await;
+    }
+
+    let app = app_router()
+        .with_state(state)
+        .layer(map_request(peer_from_request_context));
     run(app).await
 }
+
+/// API Gateway is the peer of the lambda, the address it saw stands for the connection that
+/// `ClientIp` needs
+async fn peer_from_request_context(mut request: Request) -> Request {
+    let source_ip = match request.extensions().get::<RequestContext>() {
+        Some(RequestContext::ApiGatewayV1(context)) => context.identity.source_ip.clone(),
+        Some(RequestContext::ApiGatewayV2(context)) => context.http.source_ip.clone(),
+        _ => None,
+    };
+    if let Some(ip) = source_ip.and_then(|ip| ip.parse::<IpAddr>().ok()) {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(ip, 0)));
+    }
+    request
+}
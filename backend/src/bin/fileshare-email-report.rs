@@ -0,0 +1,37 @@
+use fileshare_backend::{config::Config, services::UserService};
+use sqlx::PgPool;
+
+/// Lists the users whose email breaks the email policy, to resolve them by hand before migrating
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let _ = dotenvy::dotenv();
+    env_logger::init();
+
+    let config = Config::from_env()?;
+    let db_pool = PgPool::connect(&config.database_url)
+        .await
+        .expect("Connection to database should not fail");
+
+    let report = UserService::email_report(&db_pool, &config.email_policy).await?;
+    for duplicates in &report.duplicates {
+        println!("Duplicate accounts for {}:", duplicates.normalized_email);
+        for user in &duplicates.users {
+            println!(
+                "  {} {} created {}, verified: {}",
+                user.id,
+                user.email,
+                user.created_at,
+                user.is_verified()
+            );
+        }
+    }
+    for (user, problem) in &report.invalid {
+        println!("Invalid email for {}: {} {problem}", user.id, user.email);
+    }
+    println!(
+        "{} duplicated emails, {} invalid emails",
+        report.duplicates.len(),
+        report.invalid.len()
+    );
+    Ok(())
+}
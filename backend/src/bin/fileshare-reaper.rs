@@ -0,0 +1,19 @@
+use fileshare_backend::{config::Config, services::ReaperService, state::AppState};
+use sqlx::PgPool;
+
+/// Single pass of the reaper, meant to be run from cron or any other scheduler
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let _ = dotenvy::dotenv();
+    env_logger::init();
+
+    let config = Config::from_env()?;
+    let db_pool = PgPool::connect(&config.database_url)
+        .await
+        .expect("Connection to database should not fail");
+
+    let state = AppState::from_config(db_pool, config)?;
+    let summary = ReaperService::reap(&state).await?;
+    println!("Reaper pass done: {summary}");
+    Ok(())
+}
@@ -1,23 +1,64 @@
-use fileshare_backend::{app_router, migrate};
+use fileshare_backend::{
+    app_router, config::Config, migrate, services::ReaperService, state::AppState,
+};
 use sqlx::PgPool;
-use std::env;
+use std::{env, net::SocketAddr, path::PathBuf, process::ExitCode};
 use tokio::net::TcpListener;
 
+const USAGE: &str = "Usage: webserver [--config <file>] [--check-config]";
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     let _ = dotenvy::dotenv();
     env_logger::init();
 
-    let axum_port =
-        env::var("AXUM_PORT").map_or(3000, |e| e.parse().expect("AXUM_PORT should be a number"));
+    let mut config_file = env::var("CONFIG_FILE")
+        .ok()
+        .filter(|file| !file.is_empty())
+        .map(PathBuf::from);
+    let mut check_config = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(file) => config_file = Some(PathBuf::from(file)),
+                None => anyhow::bail!("{USAGE}"),
+            },
+            "--check-config" => check_config = true,
+            _ => anyhow::bail!("{USAGE}"),
+        }
+    }
+
+    // Fails before anything starts, with every problem at once
+    let config = match Config::load(config_file.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return Ok(ExitCode::FAILURE);
+        },
+    };
+    if check_config {
+        print!("{}", config.to_redacted_toml());
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    let db_pool = PgPool::connect(&env::var("DATABASE_URL")?)
+    let db_pool = PgPool::connect(&config.database_url)
         .await
         .expect("Connection to database should not fail");
 
-    migrate(&db_pool).await;
-    let app = app_router().with_state(db_pool);
+    migrate(&db_pool, &config.migrations_dir).await;
+    let axum_port = config.port;
+    let state = AppState::from_config(db_pool, config)?;
+    if let Some(interval) = state.config.reaper.interval {
+        tokio::spawn(ReaperService::run_periodically(state.clone(), interval));
+    }
+    let app = app_router().with_state(state);
     let listener = TcpListener::bind(format!("0.0.0.0:{axum_port}")).await?;
-    axum::serve(listener, app).await?;
-    Ok(())
+    // Gives `ClientIp` the peer address, it only reads X-Forwarded-For from trusted proxies
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+    Ok(ExitCode::SUCCESS)
 }
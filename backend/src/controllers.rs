@@ -1,5 +1,13 @@
+pub mod admin_controller;
+pub mod oidc_controller;
+pub mod share_controller;
 pub mod upload_controller;
 pub mod user_controller;
+pub mod well_known_controller;
 
+pub use admin_controller::AdminController;
+pub use oidc_controller::OidcController;
+pub use share_controller::ShareController;
 pub use upload_controller::UploadController;
 pub use user_controller::UserController;
+pub use well_known_controller::WellKnownController;
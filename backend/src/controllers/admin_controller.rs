@@ -0,0 +1,214 @@
+use crate::{
+    dtos::{PageQuery, PageResponse, UploadListQuery, UploadResponse, UserResponse},
+    entities::User,
+    services::{AuthService, UploadService, UserService},
+    state::AppState,
+    utils::{ApiError, PathExtract, QueryExtract, context_to_500},
+};
+use anyhow::Context;
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::Json,
+    routing::{delete, get, post},
+};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Controller for /api/admin, every route is reserved to admins
+pub struct AdminController {}
+impl AdminController {
+    async fn get_user(db_pool: &PgPool, id: &Uuid) -> Result<User, ApiError> {
+        UserService::from_id(db_pool, id)
+            .await
+            .with_context(|| "Failed to get user from id")
+            .map_err(context_to_500)?
+            .ok_or_else(|| ApiError::NotFound {
+                code: "user_not_found",
+                message: format!("no user with id {id}"),
+            })
+    }
+
+    /// Admins cannot lock themselves out by mistake
+    fn ensure_not_self(admin: &User, id: &Uuid) -> Result<(), ApiError> {
+        if admin.id == *id {
+            return Err(ApiError::Conflict {
+                code: "own_account",
+                message: "Admins cannot do this on their own account".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// GET /api/admin/users
+    pub async fn get_api_admin_users(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        QueryExtract(page): QueryExtract<PageQuery>,
+    ) -> Result<Json<PageResponse<UserResponse>>, ApiError> {
+        AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        let (limit, offset) = page.limit_and_offset();
+        let users = UserService::list(&state.db_pool, limit, offset)
+            .await
+            .with_context(|| "Failed to list users")
+            .map_err(context_to_500)?;
+        let total = UserService::count(&state.db_pool)
+            .await
+            .with_context(|| "Failed to count users")
+            .map_err(context_to_500)?;
+        Ok(Json(PageResponse {
+            items: users.iter().map(|u| u.into()).collect(),
+            total,
+            limit,
+            offset,
+        }))
+    }
+
+    /// GET /api/admin/users/{id}
+    pub async fn get_api_admin_users_id(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        let user = Self::get_user(&state.db_pool, &id).await?;
+        Ok(Json(user.into()))
+    }
+
+    /// DELETE /api/admin/users/{id}
+    pub async fn delete_api_admin_users_id(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<StatusCode, ApiError> {
+        let admin = AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        Self::ensure_not_self(&admin, &id)?;
+        let user = Self::get_user(&state.db_pool, &id).await?;
+        UserService::delete_user_and_uploads(&state, &user.id)
+            .await
+            .with_context(|| "Failed to delete user")
+            .map_err(context_to_500)?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// POST /api/admin/users/{id}/verify
+    pub async fn post_api_admin_users_id_verify(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        let user = Self::get_user(&state.db_pool, &id).await?;
+        if user.is_verified() {
+            return Err(ApiError::Conflict {
+                code: "already_verified",
+                message: "User is already verified".to_string(),
+            });
+        }
+        let user = UserService::force_verify(&state, &user).await?;
+        Ok(Json(user.into()))
+    }
+
+    /// POST /api/admin/users/{id}/disable
+    pub async fn post_api_admin_users_id_disable(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        let admin = AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        Self::ensure_not_self(&admin, &id)?;
+        Self::get_user(&state.db_pool, &id).await?;
+        let user = UserService::set_disabled(&state.db_pool, &id, true)
+            .await
+            .with_context(|| "Failed to disable user")
+            .map_err(context_to_500)?;
+        Ok(Json(user.into()))
+    }
+
+    /// POST /api/admin/users/{id}/enable
+    pub async fn post_api_admin_users_id_enable(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        Self::get_user(&state.db_pool, &id).await?;
+        let user = UserService::set_disabled(&state.db_pool, &id, false)
+            .await
+            .with_context(|| "Failed to enable user")
+            .map_err(context_to_500)?;
+        Ok(Json(user.into()))
+    }
+
+    /// GET /api/admin/uploads
+    pub async fn get_api_admin_uploads(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        QueryExtract(page): QueryExtract<PageQuery>,
+        QueryExtract(query): QueryExtract<UploadListQuery>,
+    ) -> Result<Json<PageResponse<UploadResponse>>, ApiError> {
+        AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        let (limit, offset) = page.limit_and_offset();
+        let uploads = UploadService::list(&state.db_pool, query.status, limit, offset)
+            .await
+            .with_context(|| "Failed to list uploads")
+            .map_err(context_to_500)?;
+        let total = UploadService::count(&state.db_pool, query.status)
+            .await
+            .with_context(|| "Failed to count uploads")
+            .map_err(context_to_500)?;
+        Ok(Json(PageResponse {
+            items: uploads.iter().map(|u| u.into()).collect(),
+            total,
+            limit,
+            offset,
+        }))
+    }
+
+    /// DELETE /api/admin/uploads/{id}
+    pub async fn delete_api_admin_uploads_id(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<StatusCode, ApiError> {
+        AuthService::get_admin_from_auth_header(&state, &headers).await?;
+        let upload = UploadService::from_id(&state.db_pool, &id)
+            .await
+            .with_context(|| "Failed to get upload from id")
+            .map_err(context_to_500)?
+            .ok_or_else(|| ApiError::NotFound {
+                code: "upload_not_found",
+                message: format!("no upload with id {id}"),
+            })?;
+        UploadService::delete_upload(&state, upload)
+            .await
+            .with_context(|| "Failed to delete upload")
+            .map_err(context_to_500)?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Router to nest in /api/admin
+    pub fn router() -> Router<AppState> {
+        Router::new()
+            .route("/uploads", get(Self::get_api_admin_uploads))
+            .route("/uploads/{id}", delete(Self::delete_api_admin_uploads_id))
+            .route("/users", get(Self::get_api_admin_users))
+            .route(
+                "/users/{id}",
+                get(Self::get_api_admin_users_id).delete(Self::delete_api_admin_users_id),
+            )
+            .route(
+                "/users/{id}/disable",
+                post(Self::post_api_admin_users_id_disable),
+            )
+            .route(
+                "/users/{id}/enable",
+                post(Self::post_api_admin_users_id_enable),
+            )
+            .route(
+                "/users/{id}/verify",
+                post(Self::post_api_admin_users_id_verify),
+            )
+    }
+}
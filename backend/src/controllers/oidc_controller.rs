@@ -0,0 +1,94 @@
+use crate::{
+    dtos::{
+        LoginResponse, LoginStartResponse, OidcCallbackRequest, OidcProviderResponse,
+        OidcStartResponse, TwoFactorChallengeResponse,
+    },
+    services::{AuthService, OidcService, TwoFactorService},
+    state::AppState,
+    utils::{ApiError, JsonExtract, PathExtract, context_to_500},
+};
+use anyhow::Context;
+use axum::{
+    Router,
+    extract::State,
+    response::Json,
+    routing::{get, post},
+};
+
+/// Controller for /api/oidc
+pub struct OidcController {}
+impl OidcController {
+    /// GET /api/oidc/providers
+    pub async fn get_api_oidc_providers(
+        State(state): State<AppState>,
+    ) -> Json<Vec<OidcProviderResponse>> {
+        Json(
+            state
+                .config
+                .oidc_providers
+                .iter()
+                .map(|provider| provider.into())
+                .collect(),
+        )
+    }
+
+    /// POST /api/oidc/{provider_id}/start
+    pub async fn post_api_oidc_provider_id_start(
+        State(state): State<AppState>,
+        PathExtract(provider_id): PathExtract<String>,
+    ) -> Result<Json<OidcStartResponse>, ApiError> {
+        let (authorization_url, browser_secret) = OidcService::start(&state, &provider_id).await?;
+        Ok(Json(OidcStartResponse {
+            authorization_url,
+            browser_secret,
+        }))
+    }
+
+    /// POST /api/oidc/callback
+    pub async fn post_api_oidc_callback(
+        State(state): State<AppState>,
+        JsonExtract(request): JsonExtract<OidcCallbackRequest>,
+    ) -> Result<Json<LoginStartResponse>, ApiError> {
+        let user_db = OidcService::finish(
+            &state,
+            &request.state,
+            &request.browser_secret,
+            &request.code,
+        )
+        .await?;
+        // The provider stands in for the password only, not for the second factor
+        if user_db.has_two_factor() {
+            let (challenge_token, expires_at) =
+                TwoFactorService::start_challenge(&state.db_pool, &user_db)
+                    .await
+                    .map_err(context_to_500)?;
+            return Ok(Json(LoginStartResponse::TwoFactor(
+                TwoFactorChallengeResponse {
+                    two_factor_required: true,
+                    challenge_token,
+                    expires_at,
+                },
+            )));
+        }
+        let tokens = AuthService::create_session_for_user(&state, &user_db)
+            .await
+            .with_context(|| "Failed to create session")
+            .map_err(context_to_500)?;
+        Ok(Json(LoginStartResponse::Tokens(LoginResponse {
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
+            user: user_db.into(),
+        })))
+    }
+
+    /// Router to nest in /api/oidc
+    pub fn router() -> Router<AppState> {
+        Router::new()
+            .route("/callback", post(Self::post_api_oidc_callback))
+            .route("/providers", get(Self::get_api_oidc_providers))
+            .route(
+                "/{provider_id}/start",
+                post(Self::post_api_oidc_provider_id_start),
+            )
+    }
+}
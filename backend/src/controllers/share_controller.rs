@@ -0,0 +1,97 @@
+use crate::{
+    dtos::{ShareUnlockRequest, ShareUnlockResponse},
+    entities::Upload,
+    services::UploadService,
+    state::AppState,
+    utils::{ApiError, JsonExtract, PathExtract, context_to_500},
+};
+use anyhow::Context;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+};
+use sqlx::PgPool;
+
+/// Controller for /s
+pub struct ShareController {}
+impl ShareController {
+    /// Gets the upload behind a share link, as long as it can still be downloaded
+    async fn get_shareable_upload(db_pool: &PgPool, share_token: &str) -> Result<Upload, ApiError> {
+        let upload_db = UploadService::from_share_token(db_pool, share_token)
+            .await
+            .with_context(|| "Failed to get upload from share token")
+            .map_err(context_to_500)?
+            .filter(|upload| upload.is_completed())
+            .ok_or_else(|| ApiError::NotFound {
+                code: "share_link_not_found",
+                message: "This link does not lead to any file".to_string(),
+            })?;
+        if !upload_db.is_shareable() {
+            return Err(ApiError::Gone {
+                code: "share_link_expired",
+                message: "This link has expired".to_string(),
+            });
+        }
+        Ok(upload_db)
+    }
+
+    /// GET /s/{share_token}
+    pub async fn get_s_share_token(
+        State(state): State<AppState>,
+        PathExtract(share_token): PathExtract<String>,
+    ) -> Result<Response, ApiError> {
+        let upload_db = Self::get_shareable_upload(&state.db_pool, &share_token).await?;
+        // Protected uploads are downloaded from a page that asks for the password first
+        if upload_db.is_password_protected() {
+            let web_host = &state.config.web_host;
+            return Ok(
+                Redirect::to(&format!("{web_host}/unlock/?token={share_token}")).into_response(),
+            );
+        }
+
+        let upload_db = UploadService::record_download(&state, upload_db).await?;
+        let presigned_get_url = UploadService::presign_download(&state, &upload_db)
+            .await
+            .with_context(|| "Failed to presign download")
+            .map_err(context_to_500)?;
+        // The redirect target expires quickly, it must not be cached
+        Ok((
+            [(header::CACHE_CONTROL, "no-store")],
+            Redirect::temporary(&presigned_get_url),
+        )
+            .into_response())
+    }
+
+    /// POST /s/{share_token}
+    pub async fn post_s_share_token(
+        State(state): State<AppState>,
+        PathExtract(share_token): PathExtract<String>,
+        JsonExtract(request): JsonExtract<ShareUnlockRequest>,
+    ) -> Result<impl IntoResponse, ApiError> {
+        let upload_db = Self::get_shareable_upload(&state.db_pool, &share_token).await?;
+        UploadService::unlock_share(&state.db_pool, &upload_db, &request.password).await?;
+
+        let upload_db = UploadService::record_download(&state, upload_db).await?;
+        let presigned_get_url = UploadService::presign_download(&state, &upload_db)
+            .await
+            .with_context(|| "Failed to presign download")
+            .map_err(context_to_500)?;
+        Ok((
+            [(header::CACHE_CONTROL, "no-store")],
+            Json(ShareUnlockResponse {
+                url: presigned_get_url,
+            }),
+        ))
+    }
+
+    /// Router to nest in /s
+    pub fn router() -> Router<AppState> {
+        Router::new().route(
+            "/{share_token}",
+            get(Self::get_s_share_token).post(Self::post_s_share_token),
+        )
+    }
+}
@@ -1,15 +1,22 @@
 use crate::{
-    dtos::{UploadResponse, UploadStartRequest, UploadStartResponse},
+    dtos::{
+        CursorPageResponse, MultipartCompleteRequest, MultipartPartRequest,
+        MultipartPresignRequest, MultipartPresignResponse, MultipartStateResponse,
+        PresignedPartResponse, UploadCompleteRequest, UploadCursor, UploadListQuery,
+        UploadPartResponse, UploadResponse, UploadStartRequest, UploadStartResponse,
+    },
+    entities::{ApiTokenScope, Upload, User},
     services::{AuthService, UploadService},
-    utils::{ApiMessage, context_to_500},
+    state::AppState,
+    utils::{ApiError, JsonExtract, PathExtract, QueryExtract, context_to_500},
 };
 use anyhow::Context;
 use axum::{
     Router,
-    extract::{Path, State},
+    extract::State,
     http::{HeaderMap, StatusCode},
     response::Json,
-    routing::{get, post},
+    routing::{get, post, put},
 };
 use sqlx::PgPool;
 use uuid::Uuid;
@@ -17,25 +24,38 @@ use uuid::Uuid;
 /// Controller for /api/uploads
 pub struct UploadController {}
 impl UploadController {
-    /// GET /api/uploads
-    pub async fn get_api_uploads(
-        State(db_pool): State<PgPool>,
-    ) -> Result<Json<Vec<UploadResponse>>, ApiMessage> {
-        let uploads = UploadService::list(&db_pool)
+    /// Gets an upload that the user is allowed to modify
+    async fn get_own_upload(db_pool: &PgPool, user: &User, id: &Uuid) -> Result<Upload, ApiError> {
+        let upload_db = UploadService::from_id(db_pool, id)
             .await
-            .with_context(|| "Failed to list uploads")
-            .map_err(context_to_500)?;
-        Ok(Json(uploads.iter().map(|u| u.into()).collect()))
+            .with_context(|| "Failed to get upload from id")
+            .map_err(context_to_500)?
+            .ok_or_else(|| ApiError::NotFound {
+                code: "upload_not_found",
+                message: format!("no upload with id {id}"),
+            })?;
+        if upload_db.user_id != Some(user.id) {
+            return Err(ApiError::Forbidden {
+                code: "not_upload_owner",
+                message: "This upload does not belong to you".to_string(),
+            });
+        }
+        Ok(upload_db)
     }
 
     /// GET /api/uploads/{id}
     pub async fn get_api_uploads_id(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-        Path(id): Path<Uuid>,
-    ) -> Result<Json<UploadResponse>, ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
-        let upload_db_opt = UploadService::from_id(&db_pool, &id)
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<UploadResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsRead),
+        )
+        .await?;
+        let upload_db_opt = UploadService::from_id(&state.db_pool, &id)
             .await
             .with_context(|| "Failed to get upload from id")
             .map_err(context_to_500)?;
@@ -46,16 +66,16 @@ impl UploadController {
                 if let Some(upload_user_id) = upload_db.user_id
                     && upload_user_id != user.id
                 {
-                    Err(ApiMessage {
-                        status: StatusCode::FORBIDDEN,
+                    Err(ApiError::Forbidden {
+                        code: "not_upload_owner",
                         message: "This upload does not belong to you".to_string(),
                     })
                 } else {
                     Ok(Json(upload_db.into()))
                 }
             },
-            None => Err(ApiMessage {
-                status: StatusCode::NOT_FOUND,
+            None => Err(ApiError::NotFound {
+                code: "upload_not_found",
                 message: format!("no upload with id {id}"),
             }),
         }
@@ -63,69 +83,277 @@ impl UploadController {
 
     /// GET /api/uploads/mine
     pub async fn get_api_uploads_mine(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-    ) -> Result<Json<Vec<UploadResponse>>, ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
-        let uploads = UploadService::from_user_id(&db_pool, &user.id)
-            .await
-            .with_context(|| "Failed to get upload from user id")
-            .map_err(context_to_500)?;
-        Ok(Json(uploads.iter().map(|u| u.into()).collect()))
+        QueryExtract(query): QueryExtract<UploadListQuery>,
+    ) -> Result<Json<CursorPageResponse<UploadResponse>>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsRead),
+        )
+        .await?;
+        let limit = query.limit();
+        // One more upload than asked tells whether there is a next page
+        let mut uploads = UploadService::from_user_id(
+            &state.db_pool,
+            &user.id,
+            &query.filter(),
+            query.sort,
+            query.after()?,
+            limit + 1,
+        )
+        .await
+        .with_context(|| "Failed to get upload from user id")
+        .map_err(context_to_500)?;
+        let next_cursor = if uploads.len() as i64 > limit {
+            uploads.truncate(limit as usize);
+            uploads.last().map(UploadCursor::encode)
+        } else {
+            None
+        };
+        Ok(Json(CursorPageResponse {
+            items: uploads.iter().map(|u| u.into()).collect(),
+            next_cursor,
+        }))
     }
 
     /// POST /api/uploads/start
     pub async fn post_api_uploads_start(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-        Json(request): Json<UploadStartRequest>,
-    ) -> Result<Json<UploadStartResponse>, ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
+        JsonExtract(request): JsonExtract<UploadStartRequest>,
+    ) -> Result<Json<UploadStartResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsWrite),
+        )
+        .await?;
         if !user.is_verified() {
-            return Err(ApiMessage::from(StatusCode::FORBIDDEN));
+            return Err(ApiError::Forbidden {
+                code: "email_not_verified",
+                message: "Verify your email before uploading".to_string(),
+            });
         }
-        let presigned_put_response = UploadService::register_new_upload_and_generate_presigned_put(
-            &db_pool,
-            user,
-            request.file_name,
-            request.content_type,
-            request.expires_at,
-        )
-        .await
-        .with_context(|| "Failed to start process for new upload")
-        .map_err(context_to_500)?;
+        request.validate(&state.config.upload_policy, false)?;
+        let (upload, presigned_put_url, presigned_post) =
+            UploadService::register_new_upload_and_generate_presigned_put(&state, user, request)
+                .await
+                .with_context(|| "Failed to start process for new upload")
+                .map_err(context_to_500)?;
         Ok(Json(UploadStartResponse {
-            url: presigned_put_response,
+            url: presigned_put_url,
+            post: presigned_post.into(),
+            upload: upload.into(),
+        }))
+    }
+
+    /// POST /api/uploads/{id}/complete
+    pub async fn post_api_uploads_id_complete(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+        JsonExtract(request): JsonExtract<UploadCompleteRequest>,
+    ) -> Result<Json<UploadResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsWrite),
+        )
+        .await?;
+        let upload_db = Self::get_own_upload(&state.db_pool, &user, &id).await?;
+
+        let upload =
+            UploadService::complete_upload(&state, upload_db, request.size_bytes, request.etag)
+                .await?;
+        Ok(Json(upload.into()))
+    }
+
+    /// POST /api/uploads/multipart/start
+    pub async fn post_api_uploads_multipart_start(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<UploadStartRequest>,
+    ) -> Result<Json<UploadResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsWrite),
+        )
+        .await?;
+        if !user.is_verified() {
+            return Err(ApiError::Forbidden {
+                code: "email_not_verified",
+                message: "Verify your email before uploading".to_string(),
+            });
+        }
+        request.validate(&state.config.upload_policy, true)?;
+        let upload = UploadService::register_new_multipart_upload(&state, user, request)
+            .await
+            .with_context(|| "Failed to start process for new multipart upload")
+            .map_err(context_to_500)?;
+        Ok(Json(upload.into()))
+    }
+
+    /// GET /api/uploads/{id}/multipart
+    pub async fn get_api_uploads_id_multipart(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<MultipartStateResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsRead),
+        )
+        .await?;
+        let upload_db = Self::get_own_upload(&state.db_pool, &user, &id).await?;
+        if !upload_db.is_multipart() {
+            return Err(ApiError::Conflict {
+                code: "not_multipart",
+                message: "This upload is not a multipart upload".to_string(),
+            });
+        }
+
+        let parts = UploadService::list_upload_parts(&state.db_pool, &id)
+            .await
+            .with_context(|| "Failed to get upload parts from upload id")
+            .map_err(context_to_500)?;
+        Ok(Json(MultipartStateResponse {
+            upload: upload_db.into(),
+            parts: parts.into_iter().map(|p| p.into()).collect(),
+        }))
+    }
+
+    /// DELETE /api/uploads/{id}/multipart
+    pub async fn delete_api_uploads_id_multipart(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<Json<UploadResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsDelete),
+        )
+        .await?;
+        let upload_db = Self::get_own_upload(&state.db_pool, &user, &id).await?;
+
+        let upload = UploadService::abort_multipart_upload(&state, upload_db).await?;
+        Ok(Json(upload.into()))
+    }
+
+    /// POST /api/uploads/{id}/multipart/parts
+    pub async fn post_api_uploads_id_multipart_parts(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+        JsonExtract(request): JsonExtract<MultipartPresignRequest>,
+    ) -> Result<Json<MultipartPresignResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsWrite),
+        )
+        .await?;
+        let upload_db = Self::get_own_upload(&state.db_pool, &user, &id).await?;
+
+        let parts =
+            UploadService::presign_upload_parts(&state, &upload_db, &request.part_numbers).await?;
+        Ok(Json(MultipartPresignResponse {
+            parts: parts
+                .into_iter()
+                .map(|(part_number, url)| PresignedPartResponse { part_number, url })
+                .collect(),
         }))
     }
 
+    /// PUT /api/uploads/{id}/multipart/parts/{part_number}
+    pub async fn put_api_uploads_id_multipart_parts_part_number(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract((id, part_number)): PathExtract<(Uuid, i32)>,
+        JsonExtract(request): JsonExtract<MultipartPartRequest>,
+    ) -> Result<Json<UploadPartResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsWrite),
+        )
+        .await?;
+        let upload_db = Self::get_own_upload(&state.db_pool, &user, &id).await?;
+
+        let part = UploadService::record_upload_part(
+            &state.db_pool,
+            &upload_db,
+            part_number,
+            &request.etag,
+            request.size_bytes,
+        )
+        .await?;
+        Ok(Json(part.into()))
+    }
+
+    /// POST /api/uploads/{id}/multipart/complete
+    pub async fn post_api_uploads_id_multipart_complete(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+        JsonExtract(request): JsonExtract<MultipartCompleteRequest>,
+    ) -> Result<Json<UploadResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsWrite),
+        )
+        .await?;
+        let upload_db = Self::get_own_upload(&state.db_pool, &user, &id).await?;
+
+        let parts = request.parts.map(|parts| {
+            parts
+                .into_iter()
+                .map(|part| (part.part_number, part.etag))
+                .collect()
+        });
+        let upload =
+            UploadService::complete_multipart_upload(&state, upload_db, parts, request.size_bytes)
+                .await?;
+        Ok(Json(upload.into()))
+    }
+
     /// DELETE /api/uploads/{id}
     pub async fn delete_api_uploads_id(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-        Path(id): Path<Uuid>,
-    ) -> Result<StatusCode, ApiMessage> {
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<StatusCode, ApiError> {
         // Verify access right
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
-        let upload_db = UploadService::from_id(&db_pool, &id)
+        let user = AuthService::get_user_from_auth_header(
+            &state,
+            &headers,
+            Some(ApiTokenScope::UploadsDelete),
+        )
+        .await?;
+        let upload_db = UploadService::from_id(&state.db_pool, &id)
             .await
             .with_context(|| "Failed to get upload from id")
             .map_err(context_to_500)?
-            .ok_or_else(|| ApiMessage {
-                status: StatusCode::NOT_FOUND,
+            .ok_or_else(|| ApiError::NotFound {
+                code: "upload_not_found",
                 message: format!("no upload with id {id}"),
             })?;
         if let Some(upload_user_id) = upload_db.user_id
             && upload_user_id != user.id
         {
-            return Err(ApiMessage {
-                status: StatusCode::FORBIDDEN,
+            return Err(ApiError::Forbidden {
+                code: "not_upload_owner",
                 message: "This upload does not belong to you".to_string(),
             });
         }
 
         // Delete the upload
-        UploadService::delete_upload(&db_pool, upload_db)
+        UploadService::delete_upload(&state, upload_db)
             .await
             .with_context(|| "Failed to delete the upload")
             .map_err(context_to_500)?;
@@ -134,14 +362,35 @@ impl UploadController {
     }
 
     /// Router to nest in /api/uploads
-    pub fn router() -> Router<PgPool> {
+    pub fn router() -> Router<AppState> {
         Router::new()
-            .route("/", get(Self::get_api_uploads))
             .route(
                 "/{id}",
                 get(Self::get_api_uploads_id).delete(Self::delete_api_uploads_id),
             )
+            .route("/{id}/complete", post(Self::post_api_uploads_id_complete))
+            .route(
+                "/{id}/multipart",
+                get(Self::get_api_uploads_id_multipart)
+                    .delete(Self::delete_api_uploads_id_multipart),
+            )
+            .route(
+                "/{id}/multipart/complete",
+                post(Self::post_api_uploads_id_multipart_complete),
+            )
+            .route(
+                "/{id}/multipart/parts",
+                post(Self::post_api_uploads_id_multipart_parts),
+            )
+            .route(
+                "/{id}/multipart/parts/{part_number}",
+                put(Self::put_api_uploads_id_multipart_parts_part_number),
+            )
             .route("/mine", get(Self::get_api_uploads_mine))
+            .route(
+                "/multipart/start",
+                post(Self::post_api_uploads_multipart_start),
+            )
             .route("/start", post(Self::post_api_uploads_start))
     }
 }
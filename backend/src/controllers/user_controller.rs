@@ -1,20 +1,24 @@
 use crate::{
     dtos::{
-        ChangePasswordRequest, LoginRequest, LoginResponse, SignUpRequest, SignUpResponse,
-        UserResponse, VerifyResponse,
+        ApiTokenCreateRequest, ApiTokenCreatedResponse, ApiTokenResponse, ChangeEmailRequest,
+        ChangeLocaleRequest, ChangePasswordRequest, LoginRequest, LoginResponse,
+        LoginStartResponse, LoginTwoFactorRequest, PasswordResetFinishRequest,
+        PasswordResetStartRequest, RefreshRequest, SendVerificationResponse, SignUpRequest,
+        SignUpResponse, TwoFactorChallengeResponse, TwoFactorConfirmRequest,
+        TwoFactorDisableRequest, TwoFactorEnrollRequest, TwoFactorEnrollmentResponse, UserResponse,
     },
-    services::{AuthService, UserService},
-    utils::{ApiMessage, context_to_500},
+    services::{ApiTokenService, AuthService, TwoFactorService, UserService},
+    state::AppState,
+    utils::{ApiError, ClientIp, JsonExtract, PathExtract, context_to_500},
 };
 use anyhow::Context;
 use axum::{
     Router,
-    extract::{Path, State},
+    extract::State,
     http::{HeaderMap, StatusCode},
     response::Json,
-    routing::{get, patch, post},
+    routing::{delete, get, patch, post},
 };
-use sqlx::PgPool;
 use uuid::Uuid;
 
 /// Controller for /api/users
@@ -22,127 +26,339 @@ pub struct UserController {}
 impl UserController {
     /// GET /api/users/me
     pub async fn get_api_users_me(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-    ) -> Result<Json<UserResponse>, ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
+    ) -> Result<Json<UserResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
         Ok(Json(user.into()))
     }
 
     /// POST /api/users/signup
     pub async fn post_api_users_signup(
-        State(db_pool): State<PgPool>,
-        Json(request): Json<SignUpRequest>,
-    ) -> Result<Json<SignUpResponse>, ApiMessage> {
-        let user_db = UserService::signup(&db_pool, &request.email, &request.password)
+        State(state): State<AppState>,
+        JsonExtract(request): JsonExtract<SignUpRequest>,
+    ) -> Result<Json<SignUpResponse>, ApiError> {
+        let user_db =
+            UserService::signup(&state, &request.email, &request.password, request.locale).await?;
+        let tokens = AuthService::create_session_for_user(&state, &user_db)
             .await
-            .with_context(|| "Failed to signup user")
+            .with_context(|| "Failed to create session")
             .map_err(context_to_500)?;
         Ok(Json(SignUpResponse {
-            token: AuthService::create_jwt_for_user(&user_db)
-                .with_context(|| "Failed to create JWT")
-                .map_err(context_to_500)?,
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
             user: user_db.into(),
         }))
     }
 
     /// POST /api/users/login
     pub async fn post_api_users_login(
-        State(db_pool): State<PgPool>,
-        Json(request): Json<LoginRequest>,
-    ) -> Result<Json<LoginResponse>, ApiMessage> {
-        match UserService::from_email(&db_pool, &request.email)
-            .await
-            .with_context(|| "Failed to get user from email")
-            .map_err(context_to_500)?
-        {
-            Some(user_db) => {
-                if bcrypt::verify(request.password, &user_db.password_hash)
-                    .with_context(|| "Failed to verify password")
-                    .map_err(context_to_500)?
-                {
-                    Ok(Json(LoginResponse {
-                        token: AuthService::create_jwt_for_user(&user_db)
-                            .with_context(|| "Failed to create JWT")
-                            .map_err(context_to_500)?,
-                        user: user_db.into(),
-                    }))
-                } else {
-                    Err(ApiMessage {
-                        status: StatusCode::UNAUTHORIZED,
-                        message: "Wrong password".to_string(),
-                    })
-                }
-            },
-            None => Err(ApiMessage {
-                status: StatusCode::UNAUTHORIZED,
-                message: "Wrong email".to_string(),
-            }),
+        State(state): State<AppState>,
+        ClientIp(client_ip): ClientIp,
+        JsonExtract(request): JsonExtract<LoginRequest>,
+    ) -> Result<Json<LoginStartResponse>, ApiError> {
+        let user_db =
+            AuthService::login(&state, &request.email, &request.password, client_ip).await?;
+        if user_db.has_two_factor() {
+            let (challenge_token, expires_at) =
+                TwoFactorService::start_challenge(&state.db_pool, &user_db)
+                    .await
+                    .map_err(context_to_500)?;
+            return Ok(Json(LoginStartResponse::TwoFactor(
+                TwoFactorChallengeResponse {
+                    two_factor_required: true,
+                    challenge_token,
+                    expires_at,
+                },
+            )));
         }
+        let tokens = AuthService::create_session_for_user(&state, &user_db)
+            .await
+            .with_context(|| "Failed to create session")
+            .map_err(context_to_500)?;
+        Ok(Json(LoginStartResponse::Tokens(LoginResponse {
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
+            user: user_db.into(),
+        })))
+    }
+
+    /// POST /api/users/login/2fa
+    pub async fn post_api_users_login_2fa(
+        State(state): State<AppState>,
+        ClientIp(client_ip): ClientIp,
+        JsonExtract(request): JsonExtract<LoginTwoFactorRequest>,
+    ) -> Result<Json<LoginResponse>, ApiError> {
+        let user_db = TwoFactorService::finish_challenge(
+            &state,
+            &request.challenge_token,
+            &request.code,
+            client_ip,
+        )
+        .await?;
+        let tokens = AuthService::create_session_for_user(&state, &user_db)
+            .await
+            .with_context(|| "Failed to create session")
+            .map_err(context_to_500)?;
+        Ok(Json(LoginResponse {
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
+            user: user_db.into(),
+        }))
     }
 
     /// POST /api/users/verify/{verification_id}
     pub async fn post_api_users_verify_verification_id(
-        State(db_pool): State<PgPool>,
-        Path(verification_id): Path<Uuid>,
-    ) -> Result<Json<VerifyResponse>, ApiMessage> {
-        let user = AuthService::verify(&db_pool, verification_id)
+        State(state): State<AppState>,
+        PathExtract(verification_id): PathExtract<Uuid>,
+    ) -> Result<Json<LoginStartResponse>, ApiError> {
+        let user_db = AuthService::verify(&state, verification_id).await?;
+        // The link only proves access to the email, users with 2FA still need their code to log in
+        if user_db.has_two_factor() {
+            let (challenge_token, expires_at) =
+                TwoFactorService::start_challenge(&state.db_pool, &user_db)
+                    .await
+                    .map_err(context_to_500)?;
+            return Ok(Json(LoginStartResponse::TwoFactor(
+                TwoFactorChallengeResponse {
+                    two_factor_required: true,
+                    challenge_token,
+                    expires_at,
+                },
+            )));
+        }
+        let tokens = AuthService::create_session_for_user(&state, &user_db)
             .await
-            .with_context(|| "Failed to verify user")
+            .with_context(|| "Failed to create session")
             .map_err(context_to_500)?;
-        Ok(Json(VerifyResponse {
-            token: AuthService::create_jwt_for_user(&user)
-                .with_context(|| "Failed to create JWT")
-                .map_err(context_to_500)?,
+        Ok(Json(LoginStartResponse::Tokens(LoginResponse {
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
+            user: user_db.into(),
+        })))
+    }
+
+    /// POST /api/users/refresh
+    pub async fn post_api_users_refresh(
+        State(state): State<AppState>,
+        JsonExtract(request): JsonExtract<RefreshRequest>,
+    ) -> Result<Json<LoginResponse>, ApiError> {
+        let (user, tokens) = AuthService::refresh_session(&state, &request.refresh_token).await?;
+        Ok(Json(LoginResponse {
+            token: tokens.token,
+            refresh_token: tokens.refresh_token,
             user: user.into(),
         }))
     }
 
+    /// POST /api/users/logout
+    pub async fn post_api_users_logout(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+    ) -> Result<StatusCode, ApiError> {
+        let (_, session) =
+            AuthService::get_user_and_session_from_auth_header(&state, &headers).await?;
+        AuthService::revoke_session(&state.db_pool, &session.id)
+            .await
+            .with_context(|| "Failed to log out")
+            .map_err(context_to_500)?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// POST /api/users/logout-everywhere
+    pub async fn post_api_users_logout_everywhere(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+    ) -> Result<StatusCode, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        AuthService::revoke_sessions_of_user(&state.db_pool, &user.id, None)
+            .await
+            .with_context(|| "Failed to log out everywhere")
+            .map_err(context_to_500)?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
     /// POST /api/users/me/send-verification
     pub async fn post_api_users_me_send_verification(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-    ) -> Result<(), ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
-        if user.is_verified() {
-            return Err(ApiMessage {
-                status: StatusCode::CONFLICT,
-                message: String::from(
-                    "User is already verified, refusing to send verification email",
-                ),
-            });
-        }
-        UserService::start_email_verification_process(&db_pool, &user)
+    ) -> Result<Json<SendVerificationResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        let next_resend_at = UserService::start_email_verification_process(&state, &user).await?;
+        Ok(Json(SendVerificationResponse { next_resend_at }))
+    }
+
+    /// POST /api/users/me/email
+    pub async fn post_api_users_me_email(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<ChangeEmailRequest>,
+    ) -> Result<StatusCode, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+
+        UserService::start_email_change(&state, &user, &request.current_password, &request.email)
+            .await?;
+
+        Ok(StatusCode::ACCEPTED)
+    }
+
+    /// POST /api/users/me/2fa
+    pub async fn post_api_users_me_2fa(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<TwoFactorEnrollRequest>,
+    ) -> Result<Json<TwoFactorEnrollmentResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        let enrollment = TwoFactorService::enroll(&state.db_pool, &user, &request.password).await?;
+        Ok(Json(TwoFactorEnrollmentResponse {
+            secret: enrollment.secret,
+            otpauth_uri: enrollment.otpauth_uri,
+            recovery_codes: enrollment.recovery_codes,
+        }))
+    }
+
+    /// POST /api/users/me/2fa/confirm
+    pub async fn post_api_users_me_2fa_confirm(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<TwoFactorConfirmRequest>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        let user_db = TwoFactorService::confirm(&state.db_pool, &user, &request.code).await?;
+        Ok(Json(user_db.into()))
+    }
+
+    /// DELETE /api/users/me/2fa
+    pub async fn delete_api_users_me_2fa(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<TwoFactorDisableRequest>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        let user_db =
+            TwoFactorService::disable(&state.db_pool, &user, &request.password, &request.code)
+                .await?;
+        Ok(Json(user_db.into()))
+    }
+
+    /// GET /api/users/me/tokens
+    pub async fn get_api_users_me_tokens(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+    ) -> Result<Json<Vec<ApiTokenResponse>>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        let api_tokens = ApiTokenService::of_user(&state.db_pool, &user)
             .await
-            .with_context(|| "Failed to send verification email")
             .map_err(context_to_500)?;
-        Ok(())
+        Ok(Json(api_tokens.into_iter().map(|t| t.into()).collect()))
     }
 
-    /// PATCH /api/users/me/password
-    pub async fn patch_api_users_me_password(
-        State(db_pool): State<PgPool>,
+    /// POST /api/users/me/tokens
+    pub async fn post_api_users_me_tokens(
+        State(state): State<AppState>,
         headers: HeaderMap,
-        Json(request): Json<ChangePasswordRequest>,
-    ) -> Result<StatusCode, ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
+        JsonExtract(request): JsonExtract<ApiTokenCreateRequest>,
+    ) -> Result<Json<ApiTokenCreatedResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        request.validate()?;
+        let (api_token, token) = ApiTokenService::create(
+            &state.db_pool,
+            &user,
+            &request.name,
+            &request.scopes,
+            request.expires_at.as_ref(),
+        )
+        .await
+        .map_err(context_to_500)?;
+        Ok(Json(ApiTokenCreatedResponse {
+            token,
+            api_token: api_token.into(),
+        }))
+    }
+
+    /// DELETE /api/users/me/tokens/{id}
+    pub async fn delete_api_users_me_tokens_id(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        PathExtract(id): PathExtract<Uuid>,
+    ) -> Result<StatusCode, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        ApiTokenService::delete(&state.db_pool, &user, &id).await?;
+        Ok(StatusCode::NO_CONTENT)
+    }
 
-        UserService::change_password(&db_pool, &user.id, request.password)
+    /// PATCH /api/users/me/locale
+    pub async fn patch_api_users_me_locale(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<ChangeLocaleRequest>,
+    ) -> Result<Json<UserResponse>, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
+        let user_db = UserService::set_locale(&state.db_pool, &user, request.locale)
             .await
-            .with_context(|| "Failed to change password")
             .map_err(context_to_500)?;
+        Ok(Json(user_db.into()))
+    }
+
+    /// PATCH /api/users/me/password
+    pub async fn patch_api_users_me_password(
+        State(state): State<AppState>,
+        headers: HeaderMap,
+        JsonExtract(request): JsonExtract<ChangePasswordRequest>,
+    ) -> Result<StatusCode, ApiError> {
+        let (user, session) =
+            AuthService::get_user_and_session_from_auth_header(&state, &headers).await?;
+
+        UserService::change_password(
+            &state,
+            &user,
+            &request.current_password,
+            &request.password,
+            &session.id,
+        )
+        .await?;
+
+        Ok(StatusCode::NO_CONTENT)
+    }
 
+    /// POST /api/users/password-reset
+    pub async fn post_api_users_password_reset(
+        State(state): State<AppState>,
+        JsonExtract(request): JsonExtract<PasswordResetStartRequest>,
+    ) -> Result<StatusCode, ApiError> {
+        // Same answer whether the account exists or not, and just as fast since the email is sent
+        // in the background
+        tokio::spawn(async move {
+            if let Err(e) = UserService::start_password_reset(&state, &request.email).await {
+                println!("Failed to start password reset, error: {e:#}");
+            }
+        });
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// POST /api/users/password-reset/{token}
+    pub async fn post_api_users_password_reset_token(
+        State(state): State<AppState>,
+        PathExtract(token): PathExtract<String>,
+        JsonExtract(request): JsonExtract<PasswordResetFinishRequest>,
+    ) -> Result<StatusCode, ApiError> {
+        UserService::finish_password_reset(&state, &token, &request.password)
+            .await?
+            .ok_or_else(|| ApiError::BadRequest {
+                code: "invalid_reset_token",
+                message: "This password reset link is invalid or expired".to_string(),
+            })?;
         Ok(StatusCode::NO_CONTENT)
     }
 
     /// DELETE /api/users/me
     pub async fn delete_api_users_me(
-        State(db_pool): State<PgPool>,
+        State(state): State<AppState>,
         headers: HeaderMap,
-    ) -> Result<StatusCode, ApiMessage> {
-        let user = AuthService::get_user_from_auth_header(&db_pool, &headers).await?;
+    ) -> Result<StatusCode, ApiError> {
+        let user = AuthService::get_user_from_auth_header(&state, &headers, None).await?;
 
-        UserService::delete_user(&db_pool, &user.id)
+        UserService::delete_user(&state.db_pool, &user.id)
             .await
             .with_context(|| "Failed to delete user")
             .map_err(context_to_500)?;
@@ -151,18 +367,45 @@ impl UserController {
     }
 
     /// Router to nest in /api/users
-    pub fn router() -> Router<PgPool> {
+    pub fn router() -> Router<AppState> {
         Router::new()
             .route("/login", post(Self::post_api_users_login))
+            .route("/login/2fa", post(Self::post_api_users_login_2fa))
+            .route("/logout", post(Self::post_api_users_logout))
+            .route(
+                "/logout-everywhere",
+                post(Self::post_api_users_logout_everywhere),
+            )
             .route(
                 "/me",
                 get(Self::get_api_users_me).delete(Self::delete_api_users_me),
             )
+            .route(
+                "/me/2fa",
+                post(Self::post_api_users_me_2fa).delete(Self::delete_api_users_me_2fa),
+            )
+            .route("/me/2fa/confirm", post(Self::post_api_users_me_2fa_confirm))
+            .route("/me/email", post(Self::post_api_users_me_email))
             .route(
                 "/me/send-verification",
                 post(Self::post_api_users_me_send_verification),
             )
+            .route("/me/locale", patch(Self::patch_api_users_me_locale))
             .route("/me/password", patch(Self::patch_api_users_me_password))
+            .route(
+                "/me/tokens",
+                get(Self::get_api_users_me_tokens).post(Self::post_api_users_me_tokens),
+            )
+            .route(
+                "/me/tokens/{id}",
+                delete(Self::delete_api_users_me_tokens_id),
+            )
+            .route("/password-reset", post(Self::post_api_users_password_reset))
+            .route(
+                "/password-reset/{token}",
+                post(Self::post_api_users_password_reset_token),
+            )
+            .route("/refresh", post(Self::post_api_users_refresh))
             .route("/signup", post(Self::post_api_users_signup))
             .route(
                 "/verify/{verification_id}",
@@ -0,0 +1,17 @@
+use crate::{services::JwtService, state::AppState};
+use axum::{Router, extract::State, response::Json, routing::get};
+use jsonwebtoken::jwk::JwkSet;
+
+/// Controller for /.well-known
+pub struct WellKnownController {}
+impl WellKnownController {
+    /// GET /.well-known/jwks.json
+    pub async fn get_well_known_jwks_json(State(state): State<AppState>) -> Json<JwkSet> {
+        Json(JwtService::jwks(&state.config.jwt))
+    }
+
+    /// Router to nest in /.well-known
+    pub fn router() -> Router<AppState> {
+        Router::new().route("/jwks.json", get(Self::get_well_known_jwks_json))
+    }
+}
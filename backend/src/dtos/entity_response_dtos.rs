@@ -1,4 +1,7 @@
-use crate::entities::{Upload, User};
+use crate::{
+    config::OidcProviderConfig,
+    entities::{ApiToken, ApiTokenScope, Locale, Upload, UploadPart, UploadStatus, User, UserRole},
+};
 use chrono::{DateTime, FixedOffset};
 use serde::Serialize;
 use uuid::Uuid;
@@ -10,6 +13,12 @@ pub struct UserResponse {
     pub updated_at: DateTime<FixedOffset>,
     pub email: String,
     pub verified: bool,
+    pub role: UserRole,
+    pub disabled: bool,
+    pub locale: Locale,
+    pub two_factor: bool,
+    /// `false` for accounts created through an identity provider, until they set a password
+    pub has_password: bool,
 }
 impl From<User> for UserResponse {
     fn from(value: User) -> Self {
@@ -18,6 +27,11 @@ impl From<User> for UserResponse {
             created_at: value.created_at,
             updated_at: value.updated_at,
             verified: value.is_verified(),
+            role: value.role,
+            disabled: value.is_disabled(),
+            two_factor: value.has_two_factor(),
+            has_password: value.password_hash.is_some(),
+            locale: value.locale,
             email: value.email,
         }
     }
@@ -30,6 +44,11 @@ impl From<&User> for UserResponse {
             updated_at: value.updated_at,
             email: value.email.clone(),
             verified: value.is_verified(),
+            role: value.role,
+            disabled: value.is_disabled(),
+            locale: value.locale,
+            two_factor: value.has_two_factor(),
+            has_password: value.password_hash.is_some(),
         }
     }
 }
@@ -42,8 +61,17 @@ pub struct UploadResponse {
     pub user_id: Option<Uuid>,
     pub file_name: String,
     pub content_type: String,
-    pub presigned_get: String,
     pub expires_at: DateTime<FixedOffset>,
+    pub status: UploadStatus,
+    pub size_bytes: Option<i64>,
+    pub etag: Option<String>,
+    pub completed_at: Option<DateTime<FixedOffset>>,
+    pub multipart: bool,
+    pub share_token: String,
+    pub password_protected: bool,
+    pub downloads: i32,
+    pub max_downloads: Option<i32>,
+    pub delete_when_exhausted: bool,
 }
 impl From<Upload> for UploadResponse {
     fn from(value: Upload) -> Self {
@@ -54,8 +82,17 @@ impl From<Upload> for UploadResponse {
             user_id: value.user_id,
             file_name: value.file_name,
             content_type: value.content_type,
-            presigned_get: value.presigned_get,
             expires_at: value.expires_at,
+            status: value.status,
+            size_bytes: value.size_bytes,
+            etag: value.etag,
+            completed_at: value.completed_at,
+            multipart: value.multipart_upload_id.is_some(),
+            share_token: value.share_token,
+            password_protected: value.password_hash.is_some(),
+            downloads: value.downloads,
+            max_downloads: value.max_downloads,
+            delete_when_exhausted: value.delete_when_exhausted,
         }
     }
 }
@@ -68,8 +105,73 @@ impl From<&Upload> for UploadResponse {
             user_id: value.user_id,
             file_name: value.file_name.clone(),
             content_type: value.content_type.clone(),
-            presigned_get: value.presigned_get.clone(),
             expires_at: value.expires_at,
+            status: value.status,
+            size_bytes: value.size_bytes,
+            etag: value.etag.clone(),
+            completed_at: value.completed_at,
+            multipart: value.multipart_upload_id.is_some(),
+            share_token: value.share_token.clone(),
+            password_protected: value.password_hash.is_some(),
+            downloads: value.downloads,
+            max_downloads: value.max_downloads,
+            delete_when_exhausted: value.delete_when_exhausted,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct UploadPartResponse {
+    pub part_number: i32,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub etag: String,
+    pub size_bytes: Option<i64>,
+}
+impl From<UploadPart> for UploadPartResponse {
+    fn from(value: UploadPart) -> Self {
+        Self {
+            part_number: value.part_number,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            etag: value.etag,
+            size_bytes: value.size_bytes,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ApiTokenResponse {
+    pub id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub name: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+}
+impl From<ApiToken> for ApiTokenResponse {
+    fn from(value: ApiToken) -> Self {
+        Self {
+            id: value.id,
+            created_at: value.created_at,
+            name: value.name,
+            scopes: value.scopes,
+            expires_at: value.expires_at,
+            last_used_at: value.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OidcProviderResponse {
+    pub id: String,
+    pub name: String,
+}
+impl From<&OidcProviderConfig> for OidcProviderResponse {
+    fn from(value: &OidcProviderConfig) -> Self {
+        Self {
+            id: value.id.clone(),
+            name: value.name.clone(),
         }
     }
 }
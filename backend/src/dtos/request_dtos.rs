@@ -1,10 +1,20 @@
-use chrono::{DateTime, FixedOffset};
+use crate::{
+    entities::{ApiTokenScope, Locale, Upload, UploadStatus},
+    repositories::{SortOrder, UploadFilter},
+    services::upload_service::{MAX_SINGLE_PUT_BYTES, UploadPolicy},
+    utils::{ApiError, FieldErrors},
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, FixedOffset, Utc};
 use serde::Deserialize;
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct SignUpRequest {
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub locale: Locale,
 }
 
 #[derive(Deserialize)]
@@ -13,14 +23,289 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct LoginTwoFactorRequest {
+    pub challenge_token: String,
+    /// TOTP code or recovery code
+    pub code: String,
+}
+
+/// What the provider sent the user back to the frontend with
+#[derive(Deserialize)]
+pub struct OidcCallbackRequest {
+    pub state: String,
+    pub code: String,
+    /// Given by /api/oidc/{provider_id}/start to the browser that started the login
+    pub browser_secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetStartRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetFinishRequest {
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Deserialize)]
 pub struct UploadStartRequest {
     pub file_name: String,
     pub expires_at: DateTime<FixedOffset>,
     pub content_type: String,
+    pub size_bytes: i64,
+    /// Optional password required to download the upload through its share link
+    pub password: Option<String>,
+    /// Number of downloads the share link allows, unlimited when missing
+    pub max_downloads: Option<i32>,
+    /// Deletes the upload once `max_downloads` is reached
+    #[serde(default)]
+    pub delete_when_exhausted: bool,
+}
+impl UploadStartRequest {
+    /// Checks the request against the upload policy, `multipart` lifts the single PUT size limit
+    pub fn validate(&self, policy: &UploadPolicy, multipart: bool) -> Result<(), ApiError> {
+        let mut field_errors = FieldErrors::default();
+        if self.file_name.is_empty() || self.file_name.contains('/') {
+            field_errors.add(
+                "file_name",
+                vec!["must be non-empty and must not contain '/'".to_string()],
+            );
+        }
+        if self.expires_at <= Utc::now() {
+            field_errors.add("expires_at", vec!["must be in the future".to_string()]);
+        }
+        if !policy.allows_content_type(&self.content_type) {
+            field_errors.add(
+                "content_type",
+                vec![format!("{} is not allowed", self.content_type)],
+            );
+        }
+        if self.size_bytes < 0 {
+            field_errors.add("size_bytes", vec!["must not be negative".to_string()]);
+        } else if self.size_bytes > policy.max_bytes {
+            field_errors.add(
+                "size_bytes",
+                vec![format!("must not be over {} bytes", policy.max_bytes)],
+            );
+        } else if !multipart && self.size_bytes > MAX_SINGLE_PUT_BYTES {
+            field_errors.add(
+                "size_bytes",
+                vec![format!(
+                    "must not be over {MAX_SINGLE_PUT_BYTES} bytes without a multipart upload"
+                )],
+            );
+        }
+        if let Some(password) = &self.password
+            && (password.is_empty() || password.len() > 72)
+        {
+            field_errors.add(
+                "password",
+                vec!["must be between 1 and 72 bytes".to_string()],
+            );
+        }
+        if self
+            .max_downloads
+            .is_some_and(|max_downloads| max_downloads < 1)
+        {
+            field_errors.add("max_downloads", vec!["must be at least 1".to_string()]);
+        }
+        if self.delete_when_exhausted && self.max_downloads.is_none() {
+            field_errors.add(
+                "delete_when_exhausted",
+                vec!["needs max_downloads".to_string()],
+            );
+        }
+        field_errors.into_result()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ShareUnlockRequest {
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct UploadCompleteRequest {
+    pub size_bytes: Option<i64>,
+    pub etag: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MultipartPresignRequest {
+    pub part_numbers: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct MultipartPartRequest {
+    pub etag: String,
+    pub size_bytes: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct MultipartCompletedPart {
+    pub part_number: i32,
+    pub etag: String,
+}
+
+#[derive(Deserialize)]
+pub struct MultipartCompleteRequest {
+    /// Parts recorded through `PUT /api/uploads/{id}/multipart/parts/{part_number}` are used when missing
+    pub parts: Option<Vec<MultipartCompletedPart>>,
+    pub size_bytes: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct UploadListQuery {
+    pub status: Option<UploadStatus>,
+    /// Prefix of the content type, like `image/`
+    pub content_type: Option<String>,
+    /// Case-insensitive substring of the file name
+    pub name: Option<String>,
+    pub expired: Option<bool>,
+    /// On `created_at`
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+}
+impl UploadListQuery {
+    pub const DEFAULT_LIMIT: i64 = 50;
+    pub const MAX_LIMIT: i64 = 200;
+
+    /// Only completed uploads are listed when no status is asked for
+    pub fn filter(&self) -> UploadFilter {
+        UploadFilter {
+            status: Some(self.status.unwrap_or(UploadStatus::Completed)),
+            content_type_prefix: self.content_type.clone(),
+            name_contains: self.name.clone(),
+            expired: self.expired,
+        }
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(Self::DEFAULT_LIMIT)
+            .clamp(1, Self::MAX_LIMIT)
+    }
+
+    pub fn after(&self) -> Result<Option<(DateTime<FixedOffset>, Uuid)>, ApiError> {
+        self.cursor
+            .as_deref()
+            .map(|cursor| {
+                UploadCursor::decode(cursor)
+                    .ok_or_else(|| ApiError::invalid_field("cursor", "is invalid"))
+            })
+            .transpose()
+    }
+}
+
+/// Opaque keyset cursor of upload listings, encodes the `(created_at, id)` of the last upload of a page
+pub struct UploadCursor {}
+impl UploadCursor {
+    pub fn encode(upload: &Upload) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(format!(
+            "{}.{}",
+            upload.created_at.timestamp_micros(),
+            upload.id
+        ))
+    }
+
+    pub fn decode(cursor: &str) -> Option<(DateTime<FixedOffset>, Uuid)> {
+        let decoded = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (micros, id) = decoded.split_once('.')?;
+        let created_at = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+        Some((created_at.fixed_offset(), Uuid::parse_str(id).ok()?))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+impl PageQuery {
+    pub const DEFAULT_LIMIT: i64 = 50;
+    pub const MAX_LIMIT: i64 = 200;
+
+    /// Limit and offset clamped to sane values
+    pub fn limit_and_offset(&self) -> (i64, i64) {
+        (
+            self.limit
+                .unwrap_or(Self::DEFAULT_LIMIT)
+                .clamp(1, Self::MAX_LIMIT),
+            self.offset.unwrap_or(0).max(0),
+        )
+    }
 }
 
 #[derive(Deserialize)]
 pub struct ChangePasswordRequest {
+    pub current_password: String,
     pub password: String,
 }
+
+#[derive(Deserialize)]
+pub struct ChangeLocaleRequest {
+    pub locale: Locale,
+}
+
+#[derive(Deserialize)]
+pub struct ChangeEmailRequest {
+    pub current_password: String,
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorEnrollRequest {
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorConfirmRequest {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorDisableRequest {
+    pub password: String,
+    /// TOTP code or recovery code
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct ApiTokenCreateRequest {
+    pub name: String,
+    pub scopes: Vec<ApiTokenScope>,
+    /// The token never expires when missing
+    pub expires_at: Option<DateTime<FixedOffset>>,
+}
+impl ApiTokenCreateRequest {
+    pub fn validate(&self) -> Result<(), ApiError> {
+        let mut field_errors = FieldErrors::default();
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 100 {
+            field_errors.add(
+                "name",
+                vec!["must be between 1 and 100 characters".to_string()],
+            );
+        }
+        if self.scopes.is_empty() {
+            field_errors.add("scopes", vec!["must not be empty".to_string()]);
+        }
+        if self
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+        {
+            field_errors.add("expires_at", vec!["must be in the future".to_string()]);
+        }
+        field_errors.into_result()
+    }
+}
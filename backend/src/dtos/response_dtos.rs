@@ -1,5 +1,10 @@
-use crate::dtos::UserResponse;
+use crate::{
+    dtos::{ApiTokenResponse, UploadPartResponse, UploadResponse, UserResponse},
+    services::upload_service::PresignedPost,
+};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(Serialize)]
 pub struct CountResponse {
@@ -14,22 +19,115 @@ pub struct MessageResponse {
 #[derive(Serialize)]
 pub struct SignUpResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserResponse,
 }
 
 #[derive(Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
     pub user: UserResponse,
 }
 
 #[derive(Serialize)]
-pub struct VerifyResponse {
+pub struct OidcStartResponse {
+    /// Page of the identity provider to send the user to
+    pub authorization_url: String,
+    /// To keep until the callback, so that a login started by someone else cannot be finished here
+    pub browser_secret: String,
+}
+
+/// Users with 2FA get a challenge instead of tokens, to answer at /api/users/login/2fa
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginStartResponse {
+    Tokens(LoginResponse),
+    TwoFactor(TwoFactorChallengeResponse),
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorChallengeResponse {
+    /// Always `true`, lets clients tell this response apart from tokens
+    pub two_factor_required: bool,
+    pub challenge_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The token is only shown once, only its hash is stored
+#[derive(Serialize)]
+pub struct ApiTokenCreatedResponse {
     pub token: String,
-    pub user: UserResponse,
+    pub api_token: ApiTokenResponse,
+}
+
+#[derive(Serialize)]
+pub struct TwoFactorEnrollmentResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub recovery_codes: Vec<String>,
 }
 
 #[derive(Serialize)]
 pub struct UploadStartResponse {
     pub url: String,
+    pub post: PresignedPostResponse,
+    pub upload: UploadResponse,
+}
+
+#[derive(Serialize)]
+pub struct PresignedPostResponse {
+    pub url: String,
+    pub fields: BTreeMap<String, String>,
+}
+impl From<PresignedPost> for PresignedPostResponse {
+    fn from(value: PresignedPost) -> Self {
+        Self {
+            url: value.url,
+            fields: value.fields,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct PresignedPartResponse {
+    pub part_number: i32,
+    pub url: String,
+}
+
+#[derive(Serialize)]
+pub struct MultipartPresignResponse {
+    pub parts: Vec<PresignedPartResponse>,
+}
+
+#[derive(Serialize)]
+pub struct MultipartStateResponse {
+    pub upload: UploadResponse,
+    pub parts: Vec<UploadPartResponse>,
+}
+
+#[derive(Serialize)]
+pub struct SendVerificationResponse {
+    pub next_resend_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ShareUnlockResponse {
+    pub url: String,
+}
+
+#[derive(Serialize)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    /// Number of items across every page
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Serialize)]
+pub struct CursorPageResponse<T> {
+    pub items: Vec<T>,
+    /// Cursor to get the next page with, `None` on the last page
+    pub next_cursor: Option<String>,
 }
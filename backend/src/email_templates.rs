@@ -0,0 +1,210 @@
+use crate::entities::Locale;
+use anyhow::{Context, bail};
+use std::{fs, io::ErrorKind, path::Path};
+
+/// Every transactional email, each one has a `{name}.txt` and a `{name}.html` template per locale.
+///
+/// The first line of the `.txt` template is the subject, the text body follows after a blank line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTemplate {
+    Verification,
+    EmailChange,
+    EmailChangeNotice,
+    PasswordReset,
+    DownloadLimitReached,
+    ExpiryWarning,
+    AccountLocked,
+}
+
+macro_rules! bundled {
+    ($locale:literal, $name:literal) => {
+        (
+            include_str!(concat!("../assets/emails/", $locale, "/", $name, ".txt")),
+            include_str!(concat!("../assets/emails/", $locale, "/", $name, ".html")),
+        )
+    };
+}
+
+impl EmailTemplate {
+    pub const ALL: [Self; 7] = [
+        Self::Verification,
+        Self::EmailChange,
+        Self::EmailChangeNotice,
+        Self::PasswordReset,
+        Self::DownloadLimitReached,
+        Self::ExpiryWarning,
+        Self::AccountLocked,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Verification => "verification",
+            Self::EmailChange => "email_change",
+            Self::EmailChangeNotice => "email_change_notice",
+            Self::PasswordReset => "password_reset",
+            Self::DownloadLimitReached => "download_limit_reached",
+            Self::ExpiryWarning => "expiry_warning",
+            Self::AccountLocked => "account_locked",
+        }
+    }
+
+    /// Text and HTML templates shipped in the binary
+    fn bundled(&self, locale: Locale) -> (&'static str, &'static str) {
+        macro_rules! per_locale {
+            ($name:literal) => {
+                match locale {
+                    Locale::En => bundled!("en", $name),
+                    Locale::Fr => bundled!("fr", $name),
+                }
+            };
+        }
+        match self {
+            Self::Verification => per_locale!("verification"),
+            Self::EmailChange => per_locale!("email_change"),
+            Self::EmailChangeNotice => per_locale!("email_change_notice"),
+            Self::PasswordReset => per_locale!("password_reset"),
+            Self::DownloadLimitReached => per_locale!("download_limit_reached"),
+            Self::ExpiryWarning => per_locale!("expiry_warning"),
+            Self::AccountLocked => per_locale!("account_locked"),
+        }
+    }
+
+    /// `{dir}/{locale}/{name}.{extension}` when the deployment overrides it, else the bundled template
+    fn load(
+        &self,
+        dir: Option<&Path>,
+        locale: Locale,
+        extension: &str,
+        bundled: &str,
+    ) -> anyhow::Result<String> {
+        let Some(dir) = dir else {
+            return Ok(bundled.to_string());
+        };
+        let path = dir
+            .join(locale.as_str())
+            .join(format!("{}.{}", self.name(), extension));
+        match fs::read_to_string(&path) {
+            Ok(template) => Ok(template),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(bundled.to_string()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Renders the template in `locale`, with the overrides of `dir` when there is one.
+    /// Every `{{ name }}` placeholder must be in `vars`.
+    pub fn render(
+        &self,
+        dir: Option<&Path>,
+        locale: Locale,
+        vars: &[(&str, &str)],
+    ) -> anyhow::Result<RenderedEmail> {
+        let (bundled_text, bundled_html) = self.bundled(locale);
+        let text = self.load(dir, locale, "txt", bundled_text)?;
+        let html = self.load(dir, locale, "html", bundled_html)?;
+
+        let (subject, text) = text
+            .split_once('\n')
+            .with_context(|| format!("Template {} has no body", self.name()))?;
+        Ok(RenderedEmail {
+            subject: fill(subject.trim(), vars, false)
+                .with_context(|| format!("Failed to render subject of {}", self.name()))?,
+            text: fill(text.trim_start_matches(['\r', '\n']), vars, false)
+                .with_context(|| format!("Failed to render text of {}", self.name()))?,
+            html: fill(&html, vars, true)
+                .with_context(|| format!("Failed to render HTML of {}", self.name()))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// Replaces every `{{ name }}` with its value, escaped when the template is HTML
+fn fill(template: &str, vars: &[(&str, &str)], escape: bool) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            bail!("Unclosed placeholder");
+        };
+        let name = rest[start + 2..start + end].trim();
+        let Some((_, value)) = vars.iter().find(|(var, _)| *var == name) else {
+            bail!("Unknown placeholder {name}");
+        };
+        if escape {
+            out.push_str(&escape_html(value));
+        } else {
+            out.push_str(value);
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_escapes_html_and_rejects_unknown_placeholders() {
+        let vars = [("name", "<b>Tom & Jerry</b>")];
+        assert_eq!(
+            fill("Hi {{ name }}!", &vars, false).unwrap(),
+            "Hi <b>Tom & Jerry</b>!"
+        );
+        assert_eq!(
+            fill("<p>Hi {{name}}!</p>", &vars, true).unwrap(),
+            "<p>Hi &lt;b&gt;Tom &amp; Jerry&lt;/b&gt;!</p>"
+        );
+        assert!(fill("Hi {{ nmae }}", &vars, false).is_err());
+        assert!(fill("Hi {{ name", &vars, false).is_err());
+    }
+
+    #[test]
+    fn bundled_templates_render_in_every_locale() {
+        // Superset of the variables `EmailService` passes, catches typos in placeholders
+        let vars = [
+            ("link", "https://fileshare.test/link"),
+            ("hours", "24"),
+            ("minutes", "60"),
+            ("current_email", "old@mail.com"),
+            ("new_email", "new@mail.com"),
+            ("file_name", "report.pdf"),
+            ("downloads", "3"),
+            ("expires_at", "2026-10-19 12:00 UTC"),
+        ];
+        for template in EmailTemplate::ALL {
+            for locale in [Locale::En, Locale::Fr] {
+                let rendered = template.render(None, locale, &vars).unwrap();
+                assert!(!rendered.subject.is_empty());
+                assert!(!rendered.text.contains("{{"));
+                assert!(
+                    rendered
+                        .html
+                        .contains(&format!("lang=\"{}\"", locale.as_str()))
+                );
+            }
+        }
+    }
+}
@@ -1,7 +1,25 @@
+pub mod api_token_entity;
+pub mod login_challenge_entity;
+pub mod login_throttle_entity;
+pub mod oidc_login_state_entity;
+pub mod password_reset_entity;
+pub mod recovery_code_entity;
+pub mod session_entity;
 pub mod upload_entity;
+pub mod upload_part_entity;
 pub mod user_entity;
+pub mod user_identity_entity;
 pub mod verification_entity;
 
-pub use upload_entity::Upload;
-pub use user_entity::User;
+pub use api_token_entity::{ApiToken, ApiTokenScope};
+pub use login_challenge_entity::LoginChallenge;
+pub use login_throttle_entity::LoginThrottle;
+pub use oidc_login_state_entity::OidcLoginState;
+pub use password_reset_entity::PasswordReset;
+pub use recovery_code_entity::RecoveryCode;
+pub use session_entity::Session;
+pub use upload_entity::{Upload, UploadStatus};
+pub use upload_part_entity::UploadPart;
+pub use user_entity::{Locale, User, UserRole};
+pub use user_identity_entity::UserIdentity;
 pub use verification_entity::Verification;
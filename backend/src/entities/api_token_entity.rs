@@ -0,0 +1,53 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// What an API token is allowed to do, routes that need no scope only accept session tokens
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type,
+)]
+#[sqlx(type_name = "TEXT")]
+pub enum ApiTokenScope {
+    #[serde(rename = "uploads:read")]
+    #[sqlx(rename = "uploads:read")]
+    UploadsRead,
+    #[serde(rename = "uploads:write")]
+    #[sqlx(rename = "uploads:write")]
+    UploadsWrite,
+    #[serde(rename = "uploads:delete")]
+    #[sqlx(rename = "uploads:delete")]
+    UploadsDelete,
+}
+impl ApiTokenScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UploadsRead => "uploads:read",
+            Self::UploadsWrite => "uploads:write",
+            Self::UploadsDelete => "uploads:delete",
+        }
+    }
+}
+
+/// Personal access token of a user, for scripts that cannot go through the login
+#[derive(Debug, FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<ApiTokenScope>,
+    pub expires_at: Option<DateTime<FixedOffset>>,
+    pub last_used_at: Option<DateTime<FixedOffset>>,
+}
+impl ApiToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    pub fn has_scope(&self, scope: ApiTokenScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
@@ -0,0 +1,21 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Password step of a login that still needs a 2FA code
+#[derive(Debug, FromRow)]
+pub struct LoginChallenge {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub token_hash: String,
+    pub expires_at: DateTime<FixedOffset>,
+    pub failed_attempts: i32,
+    pub used_at: Option<DateTime<FixedOffset>>,
+}
+impl LoginChallenge {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
@@ -0,0 +1,14 @@
+use chrono::{DateTime, FixedOffset};
+use sqlx::FromRow;
+
+/// Recent failed logins of an account or of a client address
+#[derive(Debug, FromRow)]
+pub struct LoginThrottle {
+    pub key: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    /// Failures since the last success, lockout or quiet period, reset to 0 when a lockout starts
+    pub failed_attempts: i32,
+    pub last_failed_at: DateTime<FixedOffset>,
+    pub locked_until: Option<DateTime<FixedOffset>>,
+}
@@ -0,0 +1,23 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// OIDC login sent to a provider, until the user comes back with a code
+#[derive(Debug, FromRow)]
+pub struct OidcLoginState {
+    pub id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub state_hash: String,
+    /// Hash of the secret kept by the browser that started the login, only it can finish it
+    pub browser_secret_hash: String,
+    pub provider_id: String,
+    pub nonce: String,
+    /// PKCE secret, the provider only saw its hash
+    pub code_verifier: String,
+    pub expires_at: DateTime<FixedOffset>,
+}
+impl OidcLoginState {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
@@ -0,0 +1,14 @@
+use chrono::{DateTime, FixedOffset};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, FromRow)]
+pub struct PasswordReset {
+    pub id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: DateTime<FixedOffset>,
+    pub used_at: Option<DateTime<FixedOffset>>,
+}
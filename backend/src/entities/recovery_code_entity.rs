@@ -0,0 +1,12 @@
+use chrono::{DateTime, FixedOffset};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, FromRow)]
+pub struct RecoveryCode {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub code_hash: String,
+    pub used_at: Option<DateTime<FixedOffset>>,
+}
@@ -0,0 +1,21 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A login of a user, access tokens are only valid while their session is active
+#[derive(Debug, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub refresh_token_hash: String,
+    pub previous_refresh_token_hash: Option<String>,
+    pub expires_at: DateTime<FixedOffset>,
+    pub revoked_at: Option<DateTime<FixedOffset>>,
+}
+impl Session {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at > Utc::now()
+    }
+}
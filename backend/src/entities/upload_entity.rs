@@ -1,8 +1,20 @@
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
-#[derive(Debug, FromRow)]
+/// Lifecycle of an upload: it is `Pending` until the client confirms the object reached the bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum UploadStatus {
+    Pending,
+    Completed,
+    Failed,
+    Abandoned,
+}
+
+#[derive(Debug, Clone, FromRow)]
 pub struct Upload {
     pub id: Uuid,
     pub created_at: DateTime<FixedOffset>,
@@ -10,6 +22,47 @@ pub struct Upload {
     pub user_id: Option<Uuid>,
     pub file_name: String,
     pub content_type: String,
-    pub presigned_get: String,
     pub expires_at: DateTime<FixedOffset>,
+    pub status: UploadStatus,
+    pub size_bytes: Option<i64>,
+    pub etag: Option<String>,
+    pub completed_at: Option<DateTime<FixedOffset>>,
+    pub multipart_upload_id: Option<String>,
+    pub share_token: String,
+    pub password_hash: Option<String>,
+    pub password_failed_attempts: i32,
+    pub password_locked_until: Option<DateTime<FixedOffset>>,
+    pub downloads: i32,
+    pub max_downloads: Option<i32>,
+    pub last_downloaded_at: Option<DateTime<FixedOffset>>,
+    /// Deletes the upload once every allowed download was handed out
+    pub delete_when_exhausted: bool,
+    pub expiry_warned_at: Option<DateTime<FixedOffset>>,
+}
+impl Upload {
+    pub fn object_key(&self) -> String {
+        Self::object_key_of(&self.id, &self.file_name)
+    }
+
+    /// Key of the upload in the bucket, for uploads that are not in the db yet
+    pub fn object_key_of(id: &Uuid, file_name: &str) -> String {
+        format!("content/{id}/{file_name}")
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.status == UploadStatus::Completed
+    }
+
+    /// Share links are only usable for completed uploads that did not expire yet
+    pub fn is_shareable(&self) -> bool {
+        self.is_completed() && self.expires_at > Utc::now()
+    }
+
+    pub fn is_password_protected(&self) -> bool {
+        self.password_hash.is_some()
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.multipart_upload_id.is_some()
+    }
 }
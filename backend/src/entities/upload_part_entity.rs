@@ -0,0 +1,14 @@
+use chrono::{DateTime, FixedOffset};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Part of a multipart upload that the client reported as stored in the bucket
+#[derive(Debug, FromRow)]
+pub struct UploadPart {
+    pub upload_id: Uuid,
+    pub part_number: i32,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub etag: String,
+    pub size_bytes: Option<i64>,
+}
@@ -1,18 +1,66 @@
 use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
-#[derive(Debug, FromRow)]
+/// Admins can manage every user and upload through /api/admin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum UserRole {
+    User,
+    Admin,
+}
+
+/// Language of the emails sent to the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Fr => "fr",
+        }
+    }
+}
+
+#[derive(Debug, Clone, FromRow)]
 pub struct User {
     pub id: Uuid,
     pub created_at: DateTime<FixedOffset>,
     pub updated_at: DateTime<FixedOffset>,
     pub email: String,
-    pub password_hash: String,
+    /// `None` for accounts created through an identity provider
+    pub password_hash: Option<String>,
     pub verified_with_id: Option<Uuid>,
+    pub role: UserRole,
+    pub disabled_at: Option<DateTime<FixedOffset>>,
+    pub locale: Locale,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<FixedOffset>>,
+    pub totp_last_step: Option<i64>,
 }
 impl User {
     pub fn is_verified(&self) -> bool {
         self.verified_with_id.is_some()
     }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == UserRole::Admin
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_at.is_some()
+    }
+
+    /// Logins need a TOTP or recovery code once the secret is confirmed
+    pub fn has_two_factor(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
 }
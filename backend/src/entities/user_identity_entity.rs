@@ -0,0 +1,15 @@
+use chrono::{DateTime, FixedOffset};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Account of an OpenID Connect provider that logs in as a user
+#[derive(Debug, FromRow)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+}
@@ -1,4 +1,4 @@
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Utc};
 use sqlx::FromRow;
 use uuid::Uuid;
 
@@ -9,4 +9,12 @@ pub struct Verification {
     pub updated_at: DateTime<FixedOffset>,
     pub user_id: Option<Uuid>,
     pub activated_at: Option<DateTime<FixedOffset>>,
+    /// Address the verification link was sent to, the user's email changes to it once activated
+    pub email: String,
+    pub expires_at: DateTime<FixedOffset>,
+}
+impl Verification {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
 }
@@ -1,29 +1,42 @@
-use crate::controllers::{UploadController, UserController};
+use crate::controllers::{
+    AdminController, OidcController, ShareController, UploadController, UserController,
+    WellKnownController,
+};
+use crate::state::AppState;
 use axum::Router;
 use axum::http::StatusCode;
 use sqlx::PgPool;
 use sqlx::migrate::Migrator;
-use std::{env, path::Path};
+use std::path::Path;
 
 #[cfg(test)]
 mod api_tests;
 
+pub mod config;
 pub mod controllers;
 pub mod dtos;
+pub mod email_templates;
 pub mod entities;
+pub mod mailer;
+pub mod notifier;
 pub mod repositories;
 pub mod services;
+pub mod state;
 pub mod utils;
 
-pub fn app_router() -> Router<PgPool> {
+pub fn app_router() -> Router<AppState> {
     Router::new()
+        .nest("/api/admin", AdminController::router())
+        .nest("/api/oidc", OidcController::router())
         .nest("/api/uploads", UploadController::router())
         .nest("/api/users", UserController::router())
+        .nest("/s", ShareController::router())
+        .nest("/.well-known", WellKnownController::router())
 }
 
-pub async fn migrate(db_pool: &PgPool) -> (StatusCode, String) {
-    let migrations_path = env::var("MIGRATIONS_PATH").unwrap_or("migrations".to_string());
-    match Migrator::new(Path::new(&migrations_path)).await {
+/// Applies the migrations of `migrations_dir`, see `Config::migrations_dir`
+pub async fn migrate(db_pool: &PgPool, migrations_dir: &Path) -> (StatusCode, String) {
+    match Migrator::new(migrations_dir).await {
         Ok(migrator) => match migrator.run(db_pool).await {
             Ok(_) => {
                 println!("Migrations applied successfully");
@@ -0,0 +1,218 @@
+use crate::config::{MailConfig, MailTransport, SmtpConfig};
+use anyhow::Context;
+use async_trait::async_trait;
+use lettre::{
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Mailbox, MultiPart},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+    },
+};
+use std::{
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Email as built by `EmailService` with a text and an HTML body, backends turn it into a MIME message when they need one
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+impl Email {
+    pub fn to_message(&self, from: &Mailbox) -> anyhow::Result<Message> {
+        Message::builder()
+            .from(from.clone())
+            .to(self
+                .to
+                .parse()
+                .context("Failed to parse TO email address")?)
+            .subject(&self.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                self.text.clone(),
+                self.html.clone(),
+            ))
+            .context("Failed to build email message")
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> anyhow::Result<()>;
+}
+
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    None,
+    StartTls,
+    /// Implicit TLS, usually on port 465
+    Tls,
+}
+impl SmtpTls {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::StartTls => "starttls",
+            Self::Tls => "tls",
+        }
+    }
+}
+impl FromStr for SmtpTls {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "starttls" => Ok(Self::StartTls),
+            "tls" => Ok(Self::Tls),
+            other => Err(format!("{other} should be one of none, starttls or tls")),
+        }
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<Credentials>,
+        from: Mailbox,
+    ) -> anyhow::Result<Self> {
+        let tls = match tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::StartTls => {
+                Tls::Required(TlsParameters::new(host.to_string()).context("Failed to set up TLS")?)
+            },
+            SmtpTls::Tls => {
+                Tls::Wrapper(TlsParameters::new(host.to_string()).context("Failed to set up TLS")?)
+            },
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .tls(tls);
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+
+    pub fn from_config(smtp: &SmtpConfig, from: Mailbox) -> anyhow::Result<Self> {
+        let credentials = smtp
+            .credentials
+            .clone()
+            .map(|(user, password)| Credentials::new(user, password));
+        Self::new(&smtp.host, smtp.port, smtp.tls, credentials, from)
+    }
+}
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        self.transport
+            .send(email.to_message(&self.from)?)
+            .await
+            .context("Failed to send email")?;
+        Ok(())
+    }
+}
+
+/// Drops every email as an `.eml` file in a directory, for local setups without a mail server
+pub struct FileMailer {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+impl FileMailer {
+    pub fn new(dir: impl AsRef<Path>, from: Mailbox) -> Self {
+        Self {
+            transport: AsyncFileTransport::new(dir),
+            from,
+        }
+    }
+}
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        self.transport
+            .send(email.to_message(&self.from)?)
+            .await
+            .context("Failed to write email")?;
+        Ok(())
+    }
+}
+
+/// Keeps sent emails in memory, so that tests can read them
+#[derive(Default)]
+pub struct InMemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+impl InMemoryMailer {
+    /// Emails sent to `to` so far, from the oldest
+    pub fn sent_to(&self, to: &str) -> Vec<Email> {
+        self.sent
+            .lock()
+            .expect("Mailbox lock should not be poisoned")
+            .iter()
+            .filter(|email| email.to == to)
+            .cloned()
+            .collect()
+    }
+}
+#[async_trait]
+impl Mailer for InMemoryMailer {
+    async fn send(&self, email: Email) -> anyhow::Result<()> {
+        self.sent
+            .lock()
+            .expect("Mailbox lock should not be poisoned")
+            .push(email);
+        Ok(())
+    }
+}
+
+/// Mailer picked by the `transport` of the mail config
+pub fn mailer_from_config(mail: &MailConfig) -> anyhow::Result<Arc<dyn Mailer>> {
+    Ok(match &mail.transport {
+        MailTransport::Smtp(smtp) => Arc::new(SmtpMailer::from_config(smtp, mail.from.clone())?),
+        MailTransport::File(dir) => Arc::new(FileMailer::new(dir, mail.from.clone())),
+        MailTransport::Memory => Arc::new(InMemoryMailer::default()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn file_mailer_drops_emails_in_directory() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("fileshare-mails-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let mailer = FileMailer::new(&dir, "noreply@fileshare.test".parse()?);
+
+        mailer
+            .send(Email {
+                to: "someone@mail.com".to_string(),
+                subject: "Hello".to_string(),
+                text: "Hello there".to_string(),
+                html: "<p>Hello there</p>".to_string(),
+            })
+            .await?;
+
+        let files: Vec<_> = std::fs::read_dir(&dir)?.collect::<Result<_, _>>()?;
+        assert_eq!(files.len(), 1);
+        let content = std::fs::read_to_string(files[0].path())?;
+        assert!(content.contains("Subject: Hello"));
+        assert!(content.contains("multipart/alternative"));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::config::Config;
+use anyhow::Context;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+
+/// Where team notifications go, like new signups and uploads
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, message: &str) -> anyhow::Result<()>;
+}
+
+/// Posts notifications to a Discord webhook
+pub struct DiscordNotifier {
+    client: Client,
+    webhook_url: String,
+}
+impl DiscordNotifier {
+    pub fn new(webhook_url: String) -> Self {
+        Self {
+            client: Client::new(),
+            webhook_url,
+        }
+    }
+}
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    async fn notify(&self, message: &str) -> anyhow::Result<()> {
+        self.client
+            .post(&self.webhook_url)
+            .json(&json!({
+                "content": message
+            }))
+            .send()
+            .await
+            .context("Failed to send Discord webhook message")?;
+        Ok(())
+    }
+}
+
+/// Drops every notification, when no webhook is configured
+pub struct NoopNotifier {}
+#[async_trait]
+impl Notifier for NoopNotifier {
+    async fn notify(&self, _message: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps notifications in memory, so that tests can read them
+#[derive(Default)]
+pub struct InMemoryNotifier {
+    sent: Mutex<Vec<String>>,
+}
+impl InMemoryNotifier {
+    pub fn sent(&self) -> Vec<String> {
+        self.sent
+            .lock()
+            .expect("Notifier lock should not be poisoned")
+            .clone()
+    }
+}
+#[async_trait]
+impl Notifier for InMemoryNotifier {
+    async fn notify(&self, message: &str) -> anyhow::Result<()> {
+        self.sent
+            .lock()
+            .expect("Notifier lock should not be poisoned")
+            .push(message.to_string());
+        Ok(())
+    }
+}
+
+/// Discord when a webhook is configured, notifications are disabled otherwise
+pub fn notifier_from_config(config: &Config) -> Arc<dyn Notifier> {
+    match &config.discord_webhook_url {
+        Some(webhook_url) => Arc::new(DiscordNotifier::new(webhook_url.clone())),
+        None => Arc::new(NoopNotifier {}),
+    }
+}
@@ -1,11 +1,30 @@
+use serde::Deserialize;
 use sqlx::FromRow;
 use uuid::Uuid;
 
+pub mod api_token_repository;
+pub mod login_challenge_repository;
+pub mod login_throttle_repository;
+pub mod oidc_login_state_repository;
+pub mod password_reset_repository;
+pub mod recovery_code_repository;
+pub mod session_repository;
+pub mod upload_part_repository;
 pub mod upload_repository;
+pub mod user_identity_repository;
 pub mod user_repository;
 pub mod verification_repository;
 
-pub use upload_repository::UploadRepository;
+pub use api_token_repository::ApiTokenRepository;
+pub use login_challenge_repository::LoginChallengeRepository;
+pub use login_throttle_repository::LoginThrottleRepository;
+pub use oidc_login_state_repository::OidcLoginStateRepository;
+pub use password_reset_repository::PasswordResetRepository;
+pub use recovery_code_repository::RecoveryCodeRepository;
+pub use session_repository::SessionRepository;
+pub use upload_part_repository::UploadPartRepository;
+pub use upload_repository::{UploadFilter, UploadRepository};
+pub use user_identity_repository::UserIdentityRepository;
 pub use user_repository::UserRepository;
 pub use verification_repository::VerificationRepository;
 
@@ -28,3 +47,27 @@ impl From<i64> for ReturningCount {
         Self { count: value }
     }
 }
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+impl SortOrder {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Asc => "ASC",
+            Self::Desc => "DESC",
+        }
+    }
+}
+
+/// Escapes `%`, `_` and `\` so that user input only matches literally in a LIKE pattern
+fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
@@ -0,0 +1,139 @@
+use crate::entities::{ApiToken, ApiTokenScope};
+use chrono::{DateTime, FixedOffset};
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct ApiTokenRepository {}
+impl ApiTokenRepository {
+    pub async fn insert(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        name: &str,
+        token_hash: &str,
+        scopes: &[ApiTokenScope],
+        expires_at: Option<&DateTime<FixedOffset>>,
+    ) -> Result<ApiToken, SqlxError> {
+        let res: ApiToken = sqlx::query_as(
+            "INSERT INTO api_tokens (user_id, name, token_hash, scopes, expires_at) values ($1, $2, $3, $4, $5) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(scopes)
+        .bind(expires_at)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn from_token_hash(
+        db_pool: &PgPool,
+        token_hash: &str,
+    ) -> Result<Option<ApiToken>, SqlxError> {
+        let res: Option<ApiToken> =
+            sqlx::query_as("SELECT * FROM api_tokens WHERE token_hash = $1 LIMIT 1;")
+                .bind(token_hash)
+                .fetch_optional(db_pool)
+                .await?;
+        Ok(res)
+    }
+
+    /// Tokens of a user, newest first
+    pub async fn of_user_id(db_pool: &PgPool, user_id: &Uuid) -> Result<Vec<ApiToken>, SqlxError> {
+        let res: Vec<ApiToken> = sqlx::query_as(
+            "SELECT * FROM api_tokens WHERE user_id = $1 ORDER BY created_at DESC, id DESC;",
+        )
+        .bind(user_id)
+        .fetch_all(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn set_last_used_at_now(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
+        sqlx::query("UPDATE api_tokens SET last_used_at = now() WHERE id = $1;")
+            .bind(id)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes a token of a user, `false` when the user has no token with `id`
+    pub async fn delete_from_id_of_user_id(
+        db_pool: &PgPool,
+        id: &Uuid,
+        user_id: &Uuid,
+    ) -> Result<bool, SqlxError> {
+        let res = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2;")
+            .bind(id)
+            .bind(user_id)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    pub async fn delete_of_user_id(db_pool: &PgPool, user_id: &Uuid) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM api_tokens WHERE user_id = $1;")
+            .bind(user_id)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    pub async fn delete_expired(db_pool: &PgPool) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM api_tokens WHERE expires_at <= now();")
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::ReturningId;
+    use chrono::{TimeDelta, Utc};
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn tokens_keep_their_scopes_and_only_their_owner_deletes_them(
+        db_pool: PgPool,
+    ) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let scopes = [ApiTokenScope::UploadsRead, ApiTokenScope::UploadsWrite];
+        let token =
+            ApiTokenRepository::insert(&db_pool, &user_id, "ci", "hash", &scopes, None).await?;
+        let expired_at = (Utc::now() - TimeDelta::days(1)).fixed_offset();
+        ApiTokenRepository::insert(
+            &db_pool,
+            &user_id,
+            "old",
+            "other-hash",
+            &scopes,
+            Some(&expired_at),
+        )
+        .await?;
+
+        let found = ApiTokenRepository::from_token_hash(&db_pool, "hash").await?;
+        assert!(found.is_some_and(|found| found.id == token.id && found.scopes == scopes));
+        assert_eq!(ApiTokenRepository::delete_expired(&db_pool).await?, 1);
+        assert!(
+            !ApiTokenRepository::delete_from_id_of_user_id(&db_pool, &token.id, &Uuid::new_v4())
+                .await?
+        );
+        assert!(
+            ApiTokenRepository::delete_from_id_of_user_id(&db_pool, &token.id, &user_id).await?
+        );
+        assert!(
+            ApiTokenRepository::of_user_id(&db_pool, &user_id)
+                .await?
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
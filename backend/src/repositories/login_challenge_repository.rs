@@ -0,0 +1,60 @@
+use crate::entities::LoginChallenge;
+use chrono::{DateTime, FixedOffset};
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct LoginChallengeRepository {}
+impl LoginChallengeRepository {
+    pub async fn insert(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        token_hash: &str,
+        expires_at: &DateTime<FixedOffset>,
+    ) -> Result<LoginChallenge, SqlxError> {
+        let res: LoginChallenge = sqlx::query_as(
+            "INSERT INTO login_challenges (user_id, token_hash, expires_at) values ($1, $2, $3) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn from_token_hash(
+        db_pool: &PgPool,
+        token_hash: &str,
+    ) -> Result<Option<LoginChallenge>, SqlxError> {
+        let res: Option<LoginChallenge> =
+            sqlx::query_as("SELECT * FROM login_challenges WHERE token_hash = $1 LIMIT 1;")
+                .bind(token_hash)
+                .fetch_optional(db_pool)
+                .await?;
+        Ok(res)
+    }
+
+    pub async fn record_failure(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
+        sqlx::query("UPDATE login_challenges SET updated_at = now(), failed_attempts = failed_attempts + 1 WHERE id = $1;")
+            .bind(id)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks the challenge as used, `false` when it was already used
+    pub async fn use_from_id(db_pool: &PgPool, id: &Uuid) -> Result<bool, SqlxError> {
+        let res = sqlx::query("UPDATE login_challenges SET updated_at = now(), used_at = now() WHERE id = $1 AND used_at IS NULL;")
+            .bind(id)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    pub async fn delete_expired(db_pool: &PgPool) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM login_challenges WHERE expires_at <= now();")
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
@@ -0,0 +1,176 @@
+use crate::entities::LoginThrottle;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use sqlx::{Error as SqlxError, PgConnection, PgPool};
+
+pub struct LoginThrottleRepository {}
+impl LoginThrottleRepository {
+    pub async fn from_keys(
+        db_pool: &PgPool,
+        keys: &[String],
+    ) -> Result<Vec<LoginThrottle>, SqlxError> {
+        let res: Vec<LoginThrottle> =
+            sqlx::query_as("SELECT * FROM login_throttles WHERE key = ANY($1);")
+                .bind(keys)
+                .fetch_all(db_pool)
+                .await?;
+        Ok(res)
+    }
+
+    /// Counts an attempt of `key` as failed before it is checked, so that concurrent attempts cannot
+    /// all get through. Returns `None`, counting nothing, while the key is locked or has to wait.
+    ///
+    /// Counting starts over after `window` without failures. Every `max_attempts` failures lock the
+    /// key for `lockout`. After `free_attempts`, each failure doubles the wait from `base_backoff`,
+    /// up to `max_backoff`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn reserve_attempt(
+        tx: &mut PgConnection,
+        key: &str,
+        window: &TimeDelta,
+        free_attempts: i32,
+        max_attempts: i32,
+        base_backoff: &TimeDelta,
+        max_backoff: &TimeDelta,
+        lockout: &TimeDelta,
+    ) -> Result<Option<LoginThrottle>, SqlxError> {
+        let res: Option<LoginThrottle> = sqlx::query_as("INSERT INTO login_throttles (key, failed_attempts) values ($1, 1) ON CONFLICT (key) DO UPDATE SET updated_at = now(), last_failed_at = now(), failed_attempts = CASE WHEN login_throttles.last_failed_at < now() - $2 THEN 1 WHEN login_throttles.failed_attempts + 1 >= $4 THEN 0 ELSE login_throttles.failed_attempts + 1 END, locked_until = CASE WHEN login_throttles.last_failed_at >= now() - $2 AND login_throttles.failed_attempts + 1 >= $4 THEN now() + $7 ELSE login_throttles.locked_until END WHERE login_throttles.last_failed_at < now() - $2 OR ((login_throttles.locked_until IS NULL OR login_throttles.locked_until <= now()) AND (login_throttles.failed_attempts < $3 OR login_throttles.last_failed_at + LEAST($5 * power(2, LEAST(login_throttles.failed_attempts - $3, 16)), $6) <= now())) RETURNING *;")
+            .bind(key)
+            .bind(window)
+            .bind(free_attempts)
+            .bind(max_attempts)
+            .bind(base_backoff)
+            .bind(max_backoff)
+            .bind(lockout)
+            .fetch_optional(tx)
+            .await?;
+        Ok(res)
+    }
+
+    /// Takes back an attempt reserved by `reserve_attempt` that turned out right, lifting the lock
+    /// it started if any
+    pub async fn refund_attempt(
+        db_pool: &PgPool,
+        key: &str,
+        max_attempts: i32,
+    ) -> Result<(), SqlxError> {
+        sqlx::query("UPDATE login_throttles SET updated_at = now(), failed_attempts = CASE WHEN failed_attempts = 0 AND locked_until > now() THEN $2 - 1 ELSE GREATEST(failed_attempts - 1, 0) END, locked_until = CASE WHEN failed_attempts = 0 AND locked_until > now() THEN NULL ELSE locked_until END WHERE key = $1;")
+            .bind(key)
+            .bind(max_attempts)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Restarts the wait of the keys from now, once their reserved attempts turned out wrong
+    pub async fn touch_from_keys(db_pool: &PgPool, keys: &[String]) -> Result<(), SqlxError> {
+        sqlx::query(
+            "UPDATE login_throttles SET updated_at = now(), last_failed_at = now() WHERE key = ANY($1);",
+        )
+        .bind(keys)
+        .execute(db_pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete_from_key(db_pool: &PgPool, key: &str) -> Result<(), SqlxError> {
+        sqlx::query("DELETE FROM login_throttles WHERE key = $1;")
+            .bind(key)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the keys without failures since `before`, unless they are still locked
+    pub async fn delete_quiet_since(
+        db_pool: &PgPool,
+        before: &DateTime<FixedOffset>,
+    ) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM login_throttles WHERE last_failed_at < $1 AND (locked_until IS NULL OR locked_until <= now());")
+            .bind(before)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    async fn reserve(db_pool: &PgPool, key: &str) -> Result<Option<LoginThrottle>, SqlxError> {
+        let mut conn = db_pool.acquire().await?;
+        LoginThrottleRepository::reserve_attempt(
+            &mut conn,
+            key,
+            &TimeDelta::hours(1),
+            2,
+            4,
+            &TimeDelta::seconds(30),
+            &TimeDelta::minutes(1),
+            &TimeDelta::minutes(15),
+        )
+        .await
+    }
+
+    #[sqlx::test]
+    async fn attempts_back_off_then_lock_the_key(db_pool: PgPool) -> anyhow::Result<()> {
+        for attempt in 1..3 {
+            let throttle = reserve(&db_pool, "ip:1").await?.unwrap();
+            assert_eq!(throttle.failed_attempts, attempt);
+            assert!(throttle.locked_until.is_none());
+        }
+        // Past the free attempts, the next one has to wait
+        assert!(reserve(&db_pool, "ip:1").await?.is_none());
+        sqlx::query("UPDATE login_throttles SET last_failed_at = now() - interval '31 seconds';")
+            .execute(&db_pool)
+            .await?;
+        assert_eq!(reserve(&db_pool, "ip:1").await?.unwrap().failed_attempts, 3);
+        sqlx::query("UPDATE login_throttles SET last_failed_at = now() - interval '61 seconds';")
+            .execute(&db_pool)
+            .await?;
+        let locked = reserve(&db_pool, "ip:1").await?.unwrap();
+        assert_eq!(locked.failed_attempts, 0);
+        assert!(locked.locked_until.is_some_and(|until| until > Utc::now()));
+        assert!(reserve(&db_pool, "ip:1").await?.is_none());
+
+        // A right attempt gives its reservation back, with the lock it started
+        LoginThrottleRepository::refund_attempt(&db_pool, "ip:1", 4).await?;
+        let keys = ["ip:1".to_string(), "email:unknown".to_string()];
+        let throttles = LoginThrottleRepository::from_keys(&db_pool, &keys).await?;
+        assert_eq!(throttles.len(), 1);
+        assert_eq!(throttles[0].failed_attempts, 3);
+        assert!(throttles[0].locked_until.is_none());
+
+        // Failures older than the window are forgotten
+        sqlx::query("UPDATE login_throttles SET failed_attempts = 3, last_failed_at = now() - interval '2 hours', locked_until = NULL;")
+            .execute(&db_pool)
+            .await?;
+        let throttle = reserve(&db_pool, "ip:1").await?.unwrap();
+        assert_eq!(throttle.failed_attempts, 1);
+        assert!(throttle.locked_until.is_none());
+
+        // Concurrent attempts cannot all slip through before their failures are counted
+        let attempts = tokio::join!(
+            reserve(&db_pool, "ip:2"),
+            reserve(&db_pool, "ip:2"),
+            reserve(&db_pool, "ip:2"),
+            reserve(&db_pool, "ip:2"),
+        );
+        let reserved = [attempts.0?, attempts.1?, attempts.2?, attempts.3?]
+            .iter()
+            .filter(|throttle| throttle.is_some())
+            .count();
+        assert_eq!(reserved, 2);
+
+        let deleted = LoginThrottleRepository::delete_quiet_since(
+            &db_pool,
+            &(Utc::now() + TimeDelta::minutes(1)).fixed_offset(),
+        )
+        .await?;
+        assert_eq!(deleted, 2);
+
+        Ok(())
+    }
+}
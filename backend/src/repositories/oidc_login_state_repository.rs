@@ -0,0 +1,49 @@
+use crate::entities::OidcLoginState;
+use chrono::{DateTime, FixedOffset};
+use sqlx::{Error as SqlxError, PgPool};
+
+pub struct OidcLoginStateRepository {}
+impl OidcLoginStateRepository {
+    pub async fn insert(
+        db_pool: &PgPool,
+        state_hash: &str,
+        browser_secret_hash: &str,
+        provider_id: &str,
+        nonce: &str,
+        code_verifier: &str,
+        expires_at: &DateTime<FixedOffset>,
+    ) -> Result<OidcLoginState, SqlxError> {
+        let res: OidcLoginState = sqlx::query_as(
+            "INSERT INTO oidc_login_states (state_hash, browser_secret_hash, provider_id, nonce, code_verifier, expires_at) values ($1, $2, $3, $4, $5, $6) RETURNING *;",
+        )
+        .bind(state_hash)
+        .bind(browser_secret_hash)
+        .bind(provider_id)
+        .bind(nonce)
+        .bind(code_verifier)
+        .bind(expires_at)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Deletes and returns the login of `state_hash`, so that it can only be finished once
+    pub async fn take_from_state_hash(
+        db_pool: &PgPool,
+        state_hash: &str,
+    ) -> Result<Option<OidcLoginState>, SqlxError> {
+        let res: Option<OidcLoginState> =
+            sqlx::query_as("DELETE FROM oidc_login_states WHERE state_hash = $1 RETURNING *;")
+                .bind(state_hash)
+                .fetch_optional(db_pool)
+                .await?;
+        Ok(res)
+    }
+
+    pub async fn delete_expired(db_pool: &PgPool) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM oidc_login_states WHERE expires_at <= now();")
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
@@ -0,0 +1,133 @@
+use crate::entities::PasswordReset;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct PasswordResetRepository {}
+impl PasswordResetRepository {
+    pub async fn insert(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        token_hash: &str,
+        expires_at: &DateTime<FixedOffset>,
+    ) -> Result<PasswordReset, SqlxError> {
+        let res: PasswordReset = sqlx::query_as(
+            "INSERT INTO password_resets (user_id, token_hash, expires_at) values ($1, $2, $3) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Inserts a reset unless the user got one less than `cooldown` ago, returns `None` then
+    pub async fn insert_unless_created_within(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        token_hash: &str,
+        expires_at: &DateTime<FixedOffset>,
+        cooldown: &TimeDelta,
+    ) -> Result<Option<PasswordReset>, SqlxError> {
+        let mut tx = db_pool.begin().await?;
+        // Resets of the same user wait for each other, so that they cannot all pass the cooldown
+        sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE;")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        let res: Option<PasswordReset> = sqlx::query_as(
+            "INSERT INTO password_resets (user_id, token_hash, expires_at) SELECT $1, $2, $3 WHERE NOT EXISTS (SELECT 1 FROM password_resets WHERE user_id = $1 AND created_at > now() - $4) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires_at)
+        .bind(cooldown)
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Marks a reset as used, returns `None` if it does not exist, was already used or expired
+    pub async fn use_from_token_hash(
+        db_pool: &PgPool,
+        token_hash: &str,
+    ) -> Result<Option<PasswordReset>, SqlxError> {
+        let res: Option<PasswordReset> = sqlx::query_as("UPDATE password_resets SET updated_at = now(), used_at = now() WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now() RETURNING *;")
+            .bind(token_hash)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn delete_unused_of_user_id(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+    ) -> Result<u64, SqlxError> {
+        let res =
+            sqlx::query("DELETE FROM password_resets WHERE user_id = $1 AND used_at IS NULL;")
+                .bind(user_id)
+                .execute(db_pool)
+                .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::ReturningId;
+    use chrono::Utc;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn password_reset_is_single_use_and_expires(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let tomorrow = (Utc::now() + TimeDelta::days(1)).fixed_offset();
+        let yesterday = (Utc::now() - TimeDelta::days(1)).fixed_offset();
+        PasswordResetRepository::insert(&db_pool, &user_id, "live", &tomorrow).await?;
+        PasswordResetRepository::insert(&db_pool, &user_id, "expired", &yesterday).await?;
+
+        let used = PasswordResetRepository::use_from_token_hash(&db_pool, "live").await?;
+        assert!(used.is_some_and(|reset| reset.user_id == user_id));
+        let used_again = PasswordResetRepository::use_from_token_hash(&db_pool, "live").await?;
+        assert!(used_again.is_none());
+        let expired = PasswordResetRepository::use_from_token_hash(&db_pool, "expired").await?;
+        assert!(expired.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn password_resets_wait_for_the_cooldown(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let tomorrow = (Utc::now() + TimeDelta::days(1)).fixed_offset();
+        let cooldown = TimeDelta::minutes(2);
+        let insert = |token_hash| {
+            PasswordResetRepository::insert_unless_created_within(
+                &db_pool, &user_id, token_hash, &tomorrow, &cooldown,
+            )
+        };
+
+        let (first, second) = tokio::join!(insert("first"), insert("second"));
+        assert_eq!([first?, second?].iter().flatten().count(), 1);
+
+        sqlx::query("UPDATE password_resets SET created_at = now() - interval '3 minutes';")
+            .execute(&db_pool)
+            .await?;
+        assert!(insert("third").await?.is_some());
+
+        Ok(())
+    }
+}
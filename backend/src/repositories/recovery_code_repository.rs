@@ -0,0 +1,81 @@
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct RecoveryCodeRepository {}
+impl RecoveryCodeRepository {
+    /// Replaces every recovery code of the user, used or not
+    pub async fn replace_of_user_id(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        code_hashes: &[String],
+    ) -> Result<(), SqlxError> {
+        let mut tx = db_pool.begin().await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1;")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, * FROM UNNEST($2::TEXT[]);",
+        )
+        .bind(user_id)
+        .bind(code_hashes)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    /// Marks a code as used, `false` when the user has no such unused code
+    pub async fn use_code(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        code_hash: &str,
+    ) -> Result<bool, SqlxError> {
+        let res = sqlx::query("UPDATE recovery_codes SET used_at = now() WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL;")
+            .bind(user_id)
+            .bind(code_hash)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
+    pub async fn delete_of_user_id(db_pool: &PgPool, user_id: &Uuid) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1;")
+            .bind(user_id)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::ReturningId;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn recovery_codes_are_single_use_and_replaced(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let codes = vec!["first".to_string(), "second".to_string()];
+        RecoveryCodeRepository::replace_of_user_id(&db_pool, &user_id, &codes).await?;
+
+        assert!(RecoveryCodeRepository::use_code(&db_pool, &user_id, "first").await?);
+        assert!(!RecoveryCodeRepository::use_code(&db_pool, &user_id, "first").await?);
+        assert!(!RecoveryCodeRepository::use_code(&db_pool, &Uuid::new_v4(), "second").await?);
+
+        RecoveryCodeRepository::replace_of_user_id(&db_pool, &user_id, &["third".to_string()])
+            .await?;
+        assert!(!RecoveryCodeRepository::use_code(&db_pool, &user_id, "second").await?);
+        assert_eq!(
+            RecoveryCodeRepository::delete_of_user_id(&db_pool, &user_id).await?,
+            1
+        );
+
+        Ok(())
+    }
+}
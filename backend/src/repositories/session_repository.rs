@@ -0,0 +1,128 @@
+use crate::entities::Session;
+use chrono::{DateTime, FixedOffset};
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct SessionRepository {}
+impl SessionRepository {
+    pub async fn from_id(db_pool: &PgPool, id: &Uuid) -> Result<Option<Session>, SqlxError> {
+        let res: Option<Session> = sqlx::query_as("SELECT * FROM sessions WHERE id = $1 LIMIT 1;")
+            .bind(id)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn insert(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        refresh_token_hash: &str,
+        expires_at: &DateTime<FixedOffset>,
+    ) -> Result<Session, SqlxError> {
+        let res: Session = sqlx::query_as(
+            "INSERT INTO sessions (user_id, refresh_token_hash, expires_at) values ($1, $2, $3) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(refresh_token_hash)
+        .bind(expires_at)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Swaps the refresh token of an active session, returns `None` if no active session has `refresh_token_hash`
+    pub async fn rotate(
+        db_pool: &PgPool,
+        refresh_token_hash: &str,
+        new_refresh_token_hash: &str,
+        expires_at: &DateTime<FixedOffset>,
+    ) -> Result<Option<Session>, SqlxError> {
+        let res: Option<Session> = sqlx::query_as("UPDATE sessions SET updated_at = now(), previous_refresh_token_hash = refresh_token_hash, refresh_token_hash = $1, expires_at = $2 WHERE refresh_token_hash = $3 AND revoked_at IS NULL AND expires_at > now() RETURNING *;")
+            .bind(new_refresh_token_hash)
+            .bind(expires_at)
+            .bind(refresh_token_hash)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Revokes the session that a refresh token was rotated out of, someone is replaying it
+    pub async fn revoke_from_previous_refresh_token_hash(
+        db_pool: &PgPool,
+        refresh_token_hash: &str,
+    ) -> Result<u64, SqlxError> {
+        let res = sqlx::query("UPDATE sessions SET updated_at = now(), revoked_at = now() WHERE previous_refresh_token_hash = $1 AND revoked_at IS NULL;")
+            .bind(refresh_token_hash)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    pub async fn revoke(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
+        sqlx::query(
+            "UPDATE sessions SET updated_at = now(), revoked_at = now() WHERE id = $1 AND revoked_at IS NULL;",
+        )
+        .bind(id)
+        .execute(db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Revokes every session of a user, except `keep_id` when there is one
+    pub async fn revoke_of_user_id(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        keep_id: Option<&Uuid>,
+    ) -> Result<u64, SqlxError> {
+        let res = sqlx::query("UPDATE sessions SET updated_at = now(), revoked_at = now() WHERE user_id = $1 AND revoked_at IS NULL AND ($2::UUID IS NULL OR id != $2);")
+            .bind(user_id)
+            .bind(keep_id)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Deletes sessions that can not be used anymore
+    pub async fn delete_inactive(db_pool: &PgPool) -> Result<u64, SqlxError> {
+        let res = sqlx::query(
+            "DELETE FROM sessions WHERE expires_at <= now() OR revoked_at IS NOT NULL;",
+        )
+        .execute(db_pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::ReturningId;
+    use chrono::{TimeDelta, Utc};
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn rotated_refresh_token_can_not_be_reused(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let expires_at = (Utc::now() + TimeDelta::days(1)).fixed_offset();
+        let session = SessionRepository::insert(&db_pool, &user_id, "first", &expires_at).await?;
+
+        let rotated = SessionRepository::rotate(&db_pool, "first", "second", &expires_at).await?;
+        assert!(rotated.is_some_and(|s| s.id == session.id));
+        let replayed = SessionRepository::rotate(&db_pool, "first", "third", &expires_at).await?;
+        assert!(replayed.is_none());
+
+        let revoked =
+            SessionRepository::revoke_from_previous_refresh_token_hash(&db_pool, "first").await?;
+        assert_eq!(revoked, 1);
+        let after_revoke =
+            SessionRepository::rotate(&db_pool, "second", "third", &expires_at).await?;
+        assert!(after_revoke.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use crate::entities::UploadPart;
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct UploadPartRepository {}
+impl UploadPartRepository {
+    pub async fn from_upload_id(
+        db_pool: &PgPool,
+        upload_id: &Uuid,
+    ) -> Result<Vec<UploadPart>, SqlxError> {
+        let res: Vec<UploadPart> = sqlx::query_as(
+            "SELECT * FROM upload_parts WHERE upload_id = $1 ORDER BY part_number ASC;",
+        )
+        .bind(upload_id)
+        .fetch_all(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Records a part, replacing it if the client uploaded it again
+    pub async fn upsert(
+        db_pool: &PgPool,
+        upload_id: &Uuid,
+        part_number: i32,
+        etag: &str,
+        size_bytes: Option<i64>,
+    ) -> Result<UploadPart, SqlxError> {
+        let res: UploadPart = sqlx::query_as("INSERT INTO upload_parts (upload_id, part_number, etag, size_bytes) values ($1, $2, $3, $4) ON CONFLICT (upload_id, part_number) DO UPDATE SET updated_at = now(), etag = EXCLUDED.etag, size_bytes = EXCLUDED.size_bytes RETURNING *;")
+            .bind(upload_id)
+            .bind(part_number)
+            .bind(etag)
+            .bind(size_bytes)
+            .fetch_one(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn delete_of_upload_id(db_pool: &PgPool, upload_id: &Uuid) -> Result<(), SqlxError> {
+        sqlx::query("DELETE FROM upload_parts WHERE upload_id = $1;")
+            .bind(upload_id)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::{ReturningId, UploadRepository};
+    use chrono::Utc;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn upsert_replaces_reuploaded_part(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let upload = UploadRepository::insert(
+            &db_pool,
+            &Uuid::new_v4(),
+            &user_id,
+            "file.txt",
+            "text/plain",
+            &Utc::now().fixed_offset(),
+            Some("multipart-id"),
+            "share-token",
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+        UploadPartRepository::upsert(&db_pool, &upload.id, 2, "second", Some(5)).await?;
+        UploadPartRepository::upsert(&db_pool, &upload.id, 1, "first", Some(10)).await?;
+        UploadPartRepository::upsert(&db_pool, &upload.id, 1, "first-again", Some(10)).await?;
+
+        let parts = UploadPartRepository::from_upload_id(&db_pool, &upload.id).await?;
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].part_number, 1);
+        assert_eq!(parts[0].etag, "first-again");
+        assert_eq!(parts[1].part_number, 2);
+
+        UploadRepository::delete_from_id(&db_pool, &upload.id).await?;
+        let parts_after = UploadPartRepository::from_upload_id(&db_pool, &upload.id).await?;
+        assert!(parts_after.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,17 +1,50 @@
-use crate::entities::Upload;
-use chrono::{DateTime, FixedOffset, Utc};
-use sqlx::{Error as SqlxError, PgPool};
+use crate::{
+    entities::{Upload, UploadStatus},
+    repositories::{ReturningCount, SortOrder, escape_like},
+};
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use sqlx::{Error as SqlxError, PgConnection, PgPool};
 use uuid::Uuid;
 
+/// Filters of upload listings, `None` does not filter
+#[derive(Debug, Default)]
+pub struct UploadFilter {
+    pub status: Option<UploadStatus>,
+    pub content_type_prefix: Option<String>,
+    /// Case-insensitive
+    pub name_contains: Option<String>,
+    /// `Some(false)` only keeps uploads that did not expire yet
+    pub expired: Option<bool>,
+}
+
 pub struct UploadRepository {}
 impl UploadRepository {
-    pub async fn list(db_pool: &PgPool) -> Result<Vec<Upload>, SqlxError> {
-        let res: Vec<Upload> = sqlx::query_as("SELECT * FROM uploads;")
+    /// Lists uploads from the newest, `None` lists every status
+    pub async fn list(
+        db_pool: &PgPool,
+        status: Option<UploadStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Upload>, SqlxError> {
+        let res: Vec<Upload> = sqlx::query_as("SELECT * FROM uploads WHERE ($1::TEXT IS NULL OR status = $1) ORDER BY created_at DESC, id ASC LIMIT $2 OFFSET $3;")
+            .bind(status)
+            .bind(limit)
+            .bind(offset)
             .fetch_all(db_pool)
             .await?;
         Ok(res)
     }
 
+    pub async fn count(db_pool: &PgPool, status: Option<UploadStatus>) -> Result<i64, SqlxError> {
+        let res: ReturningCount = sqlx::query_as(
+            "SELECT COUNT(*) AS count FROM uploads WHERE ($1::TEXT IS NULL OR status = $1);",
+        )
+        .bind(status)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res.count)
+    }
+
     pub async fn from_id(db_pool: &PgPool, id: &Uuid) -> Result<Option<Upload>, SqlxError> {
         let res: Option<Upload> = sqlx::query_as("SELECT * FROM uploads WHERE id = $1 LIMIT 1;")
             .bind(id)
@@ -20,7 +53,23 @@ impl UploadRepository {
 
         Ok(res)
     }
-    pub async fn from_user_id(db_pool: &PgPool, user_id: &Uuid) -> Result<Vec<Upload>, SqlxError> {
+    pub async fn from_share_token(
+        db_pool: &PgPool,
+        share_token: &str,
+    ) -> Result<Option<Upload>, SqlxError> {
+        let res: Option<Upload> =
+            sqlx::query_as("SELECT * FROM uploads WHERE share_token = $1 LIMIT 1;")
+                .bind(share_token)
+                .fetch_optional(db_pool)
+                .await?;
+        Ok(res)
+    }
+
+    /// Every upload of a user, whatever its status
+    pub async fn list_of_user_id(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+    ) -> Result<Vec<Upload>, SqlxError> {
         let res: Vec<Upload> = sqlx::query_as("SELECT * FROM uploads WHERE user_id = $1;")
             .bind(user_id)
             .fetch_all(db_pool)
@@ -28,27 +77,224 @@ impl UploadRepository {
         Ok(res)
     }
 
+    /// Page of the uploads of a user, ordered on `(created_at, id)` to page through with a keyset.
+    ///
+    /// `after` is the `(created_at, id)` of the last upload of the previous page.
+    pub async fn from_user_id(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        filter: &UploadFilter,
+        order: SortOrder,
+        after: Option<(DateTime<FixedOffset>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<Upload>, SqlxError> {
+        let (after_created_at, after_id) = after.unzip();
+        let keyset_comparison = match order {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        };
+        let query = format!(
+            "SELECT * FROM uploads WHERE user_id = $1 \
+            AND ($2::TEXT IS NULL OR status = $2) \
+            AND ($3::TEXT IS NULL OR content_type LIKE $3 || '%') \
+            AND ($4::TEXT IS NULL OR file_name ILIKE '%' || $4 || '%') \
+            AND ($5::BOOLEAN IS NULL OR (expires_at <= now()) = $5) \
+            AND ($6::TIMESTAMPTZ IS NULL OR (created_at, id) {keyset_comparison} ($6, $7)) \
+            ORDER BY created_at {order}, id {order} LIMIT $8;",
+            order = order.as_sql(),
+        );
+        let res: Vec<Upload> = sqlx::query_as(&query)
+            .bind(user_id)
+            .bind(filter.status)
+            .bind(filter.content_type_prefix.as_deref().map(escape_like))
+            .bind(filter.name_contains.as_deref().map(escape_like))
+            .bind(filter.expired)
+            .bind(after_created_at)
+            .bind(after_id)
+            .bind(limit)
+            .fetch_all(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn insert(
         db_pool: &PgPool,
         id: &Uuid,
         user_id: &Uuid,
         file_name: &str,
         content_type: &str,
-        presigned_get: &str,
         expires_at: &DateTime<FixedOffset>,
+        multipart_upload_id: Option<&str>,
+        share_token: &str,
+        password_hash: Option<&str>,
+        max_downloads: Option<i32>,
+        delete_when_exhausted: bool,
     ) -> Result<Upload, SqlxError> {
-        let res: Upload = sqlx::query_as("INSERT INTO uploads (id, user_id, file_name, content_type, presigned_get, expires_at) values ($1, $2, $3, $4, $5, $6) RETURNING *;")
+        let res: Upload = sqlx::query_as("INSERT INTO uploads (id, user_id, file_name, content_type, expires_at, multipart_upload_id, share_token, password_hash, max_downloads, delete_when_exhausted) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *;")
             .bind(id)
             .bind(user_id)
             .bind(file_name)
             .bind(content_type)
-            .bind(presigned_get)
             .bind(expires_at)
+            .bind(multipart_upload_id)
+            .bind(share_token)
+            .bind(password_hash)
+            .bind(max_downloads)
+            .bind(delete_when_exhausted)
             .fetch_one(db_pool)
             .await?;
         Ok(res)
     }
 
+    /// Only moves a pending upload, returns `None` if it was not pending anymore
+    pub async fn set_completed(
+        db_pool: &PgPool,
+        id: &Uuid,
+        size_bytes: i64,
+        etag: &str,
+        content_type: &str,
+    ) -> Result<Option<Upload>, SqlxError> {
+        let res: Option<Upload> = sqlx::query_as("UPDATE uploads SET updated_at = now(), completed_at = now(), status = 'completed', size_bytes = $1, etag = $2, content_type = $3 WHERE id = $4 AND status = 'pending' RETURNING *;")
+            .bind(size_bytes)
+            .bind(etag)
+            .bind(content_type)
+            .bind(id)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Only moves a pending upload, returns `None` if it was not pending anymore
+    pub async fn set_failed(db_pool: &PgPool, id: &Uuid) -> Result<Option<Upload>, SqlxError> {
+        let res: Option<Upload> = sqlx::query_as(
+            "UPDATE uploads SET updated_at = now(), status = 'failed' WHERE id = $1 AND status = 'pending' RETURNING *;",
+        )
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Only moves a pending upload, returns `None` if it was not pending anymore
+    pub async fn set_abandoned(db_pool: &PgPool, id: &Uuid) -> Result<Option<Upload>, SqlxError> {
+        let res: Option<Upload> = sqlx::query_as(
+            "UPDATE uploads SET updated_at = now(), status = 'abandoned' WHERE id = $1 AND status = 'pending' RETURNING *;",
+        )
+        .bind(id)
+        .fetch_optional(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Counts a password attempt as wrong before it is checked, so that concurrent guesses cannot
+    /// all get through. Returns `None`, counting nothing, while the upload is locked.
+    ///
+    /// Every `max_attempts` failures lock the upload for `lockout`.
+    pub async fn claim_password_attempt(
+        db_pool: &PgPool,
+        id: &Uuid,
+        max_attempts: i32,
+        lockout: &TimeDelta,
+    ) -> Result<Option<Upload>, SqlxError> {
+        let res: Option<Upload> = sqlx::query_as("UPDATE uploads SET updated_at = now(), password_failed_attempts = CASE WHEN password_failed_attempts + 1 >= $1 THEN 0 ELSE password_failed_attempts + 1 END, password_locked_until = CASE WHEN password_failed_attempts + 1 >= $1 THEN now() + $2 ELSE password_locked_until END WHERE id = $3 AND (password_locked_until IS NULL OR password_locked_until <= now()) RETURNING *;")
+            .bind(max_attempts)
+            .bind(lockout)
+            .bind(id)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Forgets the failures once the right password is given, with the lock its attempt started
+    pub async fn reset_password_failures(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
+        sqlx::query(
+            "UPDATE uploads SET updated_at = now(), password_failed_attempts = 0, password_locked_until = NULL WHERE id = $1;",
+        )
+        .bind(id)
+        .execute(db_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Counts a download, returns `None` if the upload already reached its download limit
+    pub async fn increment_downloads(
+        db_pool: &PgPool,
+        id: &Uuid,
+    ) -> Result<Option<Upload>, SqlxError> {
+        let res: Option<Upload> = sqlx::query_as("UPDATE uploads SET updated_at = now(), last_downloaded_at = now(), downloads = downloads + 1 WHERE id = $1 AND (max_downloads IS NULL OR downloads < max_downloads) RETURNING *;")
+            .bind(id)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Moves every upload still pending since before `created_before`, returns how many were moved
+    pub async fn set_abandoned_created_before(
+        db_pool: &PgPool,
+        created_before: &DateTime<FixedOffset>,
+    ) -> Result<u64, SqlxError> {
+        let res = sqlx::query(
+            "UPDATE uploads SET updated_at = now(), status = 'abandoned' WHERE status = 'pending' AND created_at < $1;",
+        )
+        .bind(created_before)
+        .execute(db_pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Marks completed uploads expiring before `expiring_before` as warned, and returns them.
+    ///
+    /// Uploads that never lived longer than `window` are left alone, their owner knows they are short-lived.
+    pub async fn set_expiry_warned_expiring_before(
+        db_pool: &PgPool,
+        expiring_before: &DateTime<FixedOffset>,
+        window: &TimeDelta,
+    ) -> Result<Vec<Upload>, SqlxError> {
+        let res: Vec<Upload> = sqlx::query_as(
+            "UPDATE uploads SET expiry_warned_at = now() WHERE expiry_warned_at IS NULL AND status = 'completed' AND user_id IS NOT NULL AND expires_at > now() AND expires_at <= $1 AND expires_at - created_at > $2 RETURNING *;",
+        )
+        .bind(expiring_before)
+        .bind(window)
+        .fetch_all(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn unset_expiry_warned(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
+        sqlx::query("UPDATE uploads SET expiry_warned_at = NULL WHERE id = $1;")
+            .bind(id)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Locks expired uploads until the end of the transaction, rows locked by another transaction are skipped.
+    ///
+    /// Uploads to delete once exhausted are locked too, when their last download happened before `exhausted_before`.
+    pub async fn lock_expired(
+        tx: &mut PgConnection,
+        exhausted_before: &DateTime<FixedOffset>,
+        limit: i64,
+    ) -> Result<Vec<Upload>, SqlxError> {
+        let res: Vec<Upload> = sqlx::query_as(
+            "SELECT * FROM uploads WHERE expires_at <= now() OR (delete_when_exhausted AND downloads >= max_downloads AND last_downloaded_at < $1) ORDER BY expires_at ASC LIMIT $2 FOR UPDATE SKIP LOCKED;",
+        )
+        .bind(exhausted_before)
+        .bind(limit)
+        .fetch_all(tx)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn delete_from_ids(tx: &mut PgConnection, ids: &[Uuid]) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM uploads WHERE id = ANY($1);")
+            .bind(ids)
+            .execute(tx)
+            .await?;
+        Ok(res.rows_affected())
+    }
+
     pub async fn delete_from_id(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
         let now = Utc::now().fixed_offset();
         sqlx::query("DELETE FROM uploads WHERE id = $2 RETURNING id;")
@@ -59,3 +305,378 @@ impl UploadRepository {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::ReturningId;
+
+    use super::*;
+
+    async fn insert_user(db_pool: &PgPool) -> anyhow::Result<Uuid> {
+        let user_id = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(db_pool)
+        .await?
+        .id;
+        Ok(user_id)
+    }
+
+    /// Fields of a test upload that tests care about
+    struct NewUpload<'a> {
+        file_name: &'a str,
+        content_type: &'a str,
+        /// From now, uploads are expired by default
+        expires_in: TimeDelta,
+        share_token: &'a str,
+        password_hash: Option<&'a str>,
+        max_downloads: Option<i32>,
+        delete_when_exhausted: bool,
+    }
+    impl Default for NewUpload<'_> {
+        fn default() -> Self {
+            Self {
+                file_name: "file.txt",
+                content_type: "text/plain",
+                expires_in: TimeDelta::zero(),
+                share_token: "share-token",
+                password_hash: None,
+                max_downloads: None,
+                delete_when_exhausted: false,
+            }
+        }
+    }
+
+    async fn insert_upload(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        upload: NewUpload<'_>,
+    ) -> anyhow::Result<Upload> {
+        let upload = UploadRepository::insert(
+            db_pool,
+            &Uuid::new_v4(),
+            user_id,
+            upload.file_name,
+            upload.content_type,
+            &(Utc::now() + upload.expires_in).fixed_offset(),
+            None,
+            upload.share_token,
+            upload.password_hash,
+            upload.max_downloads,
+            upload.delete_when_exhausted,
+        )
+        .await?;
+        Ok(upload)
+    }
+
+    #[sqlx::test]
+    async fn set_completed_only_moves_pending_uploads(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        let upload = insert_upload(&db_pool, &user_id, NewUpload::default()).await?;
+        assert_eq!(upload.status, UploadStatus::Pending);
+
+        let completed =
+            UploadRepository::set_completed(&db_pool, &upload.id, 4, "etag", "text/plain").await?;
+        assert!(completed.is_some_and(|u| u.is_completed() && u.size_bytes == Some(4)));
+
+        let completed_again =
+            UploadRepository::set_completed(&db_pool, &upload.id, 4, "etag", "text/plain").await?;
+        assert!(completed_again.is_none());
+        let failed = UploadRepository::set_failed(&db_pool, &upload.id).await?;
+        assert!(failed.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn from_user_id_filters_on_status(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        let upload = insert_upload(&db_pool, &user_id, NewUpload::default()).await?;
+
+        let completed_filter = UploadFilter {
+            status: Some(UploadStatus::Completed),
+            ..Default::default()
+        };
+        let completed = UploadRepository::from_user_id(
+            &db_pool,
+            &user_id,
+            &completed_filter,
+            SortOrder::Desc,
+            None,
+            10,
+        )
+        .await?;
+        assert!(completed.is_empty());
+        let pending_filter = UploadFilter {
+            status: Some(UploadStatus::Pending),
+            ..Default::default()
+        };
+        let pending = UploadRepository::from_user_id(
+            &db_pool,
+            &user_id,
+            &pending_filter,
+            SortOrder::Desc,
+            None,
+            10,
+        )
+        .await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, upload.id);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn from_user_id_pages_through_filtered_uploads(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        for (file_name, content_type, share_token) in [
+            ("photo_1.png", "image/png", "first"),
+            ("photo%2.jpg", "image/jpeg", "second"),
+            ("PHOTO_3.png", "image/png", "third"),
+            ("notes.txt", "text/plain", "fourth"),
+        ] {
+            insert_upload(
+                &db_pool,
+                &user_id,
+                NewUpload {
+                    file_name,
+                    content_type,
+                    expires_in: TimeDelta::days(1),
+                    share_token,
+                    ..Default::default()
+                },
+            )
+            .await?;
+        }
+
+        let filter = UploadFilter {
+            content_type_prefix: Some("image/".to_string()),
+            name_contains: Some("photo_".to_string()),
+            expired: Some(false),
+            ..Default::default()
+        };
+        let first_page =
+            UploadRepository::from_user_id(&db_pool, &user_id, &filter, SortOrder::Asc, None, 1)
+                .await?;
+        assert_eq!(first_page.len(), 1);
+        assert_eq!(first_page[0].file_name, "photo_1.png");
+        let last = &first_page[0];
+        let second_page = UploadRepository::from_user_id(
+            &db_pool,
+            &user_id,
+            &filter,
+            SortOrder::Asc,
+            Some((last.created_at, last.id)),
+            10,
+        )
+        .await?;
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(second_page[0].file_name, "PHOTO_3.png");
+
+        let expired_filter = UploadFilter {
+            expired: Some(true),
+            ..Default::default()
+        };
+        let expired = UploadRepository::from_user_id(
+            &db_pool,
+            &user_id,
+            &expired_filter,
+            SortOrder::Desc,
+            None,
+            10,
+        )
+        .await?;
+        assert!(expired.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn lock_expired_skips_uploads_locked_elsewhere(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        for share_token in ["first", "second"] {
+            let upload = NewUpload {
+                share_token,
+                ..Default::default()
+            };
+            insert_upload(&db_pool, &user_id, upload).await?;
+        }
+        let alive = NewUpload {
+            expires_in: TimeDelta::days(1),
+            share_token: "alive",
+            ..Default::default()
+        };
+        insert_upload(&db_pool, &user_id, alive).await?;
+
+        let mut first_tx = db_pool.begin().await?;
+        let first_batch =
+            UploadRepository::lock_expired(&mut first_tx, &Utc::now().fixed_offset(), 1).await?;
+        assert_eq!(first_batch.len(), 1);
+
+        let mut second_tx = db_pool.begin().await?;
+        let second_batch =
+            UploadRepository::lock_expired(&mut second_tx, &Utc::now().fixed_offset(), 10).await?;
+        assert_eq!(second_batch.len(), 1);
+        assert_ne!(second_batch[0].id, first_batch[0].id);
+
+        let deleted =
+            UploadRepository::delete_from_ids(&mut second_tx, &[second_batch[0].id]).await?;
+        assert_eq!(deleted, 1);
+        second_tx.commit().await?;
+        first_tx.rollback().await?;
+
+        let remaining =
+            UploadRepository::list(&db_pool, Some(UploadStatus::Pending), 10, 0).await?;
+        assert_eq!(remaining.len(), 2);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn set_abandoned_created_before_only_moves_old_pending_uploads(
+        db_pool: PgPool,
+    ) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        let upload = insert_upload(&db_pool, &user_id, NewUpload::default()).await?;
+
+        let before_creation = (Utc::now() - TimeDelta::hours(1)).fixed_offset();
+        let abandoned =
+            UploadRepository::set_abandoned_created_before(&db_pool, &before_creation).await?;
+        assert_eq!(abandoned, 0);
+
+        let after_creation = (Utc::now() + TimeDelta::hours(1)).fixed_offset();
+        let abandoned =
+            UploadRepository::set_abandoned_created_before(&db_pool, &after_creation).await?;
+        assert_eq!(abandoned, 1);
+        let upload = UploadRepository::from_id(&db_pool, &upload.id)
+            .await?
+            .unwrap();
+        assert_eq!(upload.status, UploadStatus::Abandoned);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn increment_downloads_stops_at_max_downloads(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        let upload = NewUpload {
+            expires_in: TimeDelta::days(1),
+            max_downloads: Some(2),
+            delete_when_exhausted: true,
+            ..Default::default()
+        };
+        let upload = insert_upload(&db_pool, &user_id, upload).await?;
+
+        let first = UploadRepository::increment_downloads(&db_pool, &upload.id).await?;
+        assert!(first.is_some_and(|u| u.downloads == 1));
+        let second = UploadRepository::increment_downloads(&db_pool, &upload.id).await?;
+        assert!(second.is_some_and(|u| u.downloads == 2));
+        let third = UploadRepository::increment_downloads(&db_pool, &upload.id).await?;
+        assert!(third.is_none());
+
+        // Exhausted uploads are only reaped once their last download is old enough
+        let mut tx = db_pool.begin().await?;
+        let before_download = (Utc::now() - TimeDelta::hours(1)).fixed_offset();
+        let locked = UploadRepository::lock_expired(&mut tx, &before_download, 10).await?;
+        assert!(locked.is_empty());
+        let after_download = (Utc::now() + TimeDelta::hours(1)).fixed_offset();
+        let locked = UploadRepository::lock_expired(&mut tx, &after_download, 10).await?;
+        assert_eq!(locked.len(), 1);
+        tx.rollback().await?;
+
+        // Later updates of the upload do not push back the reaping
+        sqlx::query("UPDATE uploads SET last_downloaded_at = now() - interval '2 hours';")
+            .execute(&db_pool)
+            .await?;
+        UploadRepository::reset_password_failures(&db_pool, &upload.id).await?;
+        let mut tx = db_pool.begin().await?;
+        let locked = UploadRepository::lock_expired(&mut tx, &before_download, 10).await?;
+        assert_eq!(locked.len(), 1);
+        tx.rollback().await?;
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn claim_password_attempt_refuses_locked_uploads(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        let upload = NewUpload {
+            expires_in: TimeDelta::days(1),
+            password_hash: Some("hash"),
+            ..Default::default()
+        };
+        let upload = insert_upload(&db_pool, &user_id, upload).await?;
+        let lockout = TimeDelta::minutes(15);
+
+        let first =
+            UploadRepository::claim_password_attempt(&db_pool, &upload.id, 2, &lockout).await?;
+        assert!(first.is_some_and(|u| u.password_failed_attempts == 1));
+        let locking =
+            UploadRepository::claim_password_attempt(&db_pool, &upload.id, 2, &lockout).await?;
+        assert!(locking.is_some_and(|u| u.password_locked_until.is_some()));
+        let refused =
+            UploadRepository::claim_password_attempt(&db_pool, &upload.id, 2, &lockout).await?;
+        assert!(refused.is_none());
+
+        // The right password lifts the lock its own attempt started
+        UploadRepository::reset_password_failures(&db_pool, &upload.id).await?;
+        let claimed =
+            UploadRepository::claim_password_attempt(&db_pool, &upload.id, 2, &lockout).await?;
+        assert!(claimed.is_some_and(|u| u.password_failed_attempts == 1));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn set_expiry_warned_only_warns_once(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id = insert_user(&db_pool).await?;
+        let mut upload_ids = vec![];
+        for hours in [2, 48] {
+            let share_token = format!("share-token-{hours}");
+            let upload = NewUpload {
+                expires_in: TimeDelta::hours(hours),
+                share_token: &share_token,
+                ..Default::default()
+            };
+            let upload = insert_upload(&db_pool, &user_id, upload).await?;
+            UploadRepository::set_completed(&db_pool, &upload.id, 4, "etag", "text/plain").await?;
+            upload_ids.push(upload.id);
+        }
+        // Pretend both uploads were created a week ago
+        sqlx::query("UPDATE uploads SET created_at = now() - interval '7 days';")
+            .execute(&db_pool)
+            .await?;
+
+        let expiring_before = (Utc::now() + TimeDelta::days(1)).fixed_offset();
+        let window = TimeDelta::days(1);
+        let warned = UploadRepository::set_expiry_warned_expiring_before(
+            &db_pool,
+            &expiring_before,
+            &window,
+        )
+        .await?;
+        assert_eq!(warned.len(), 1);
+        assert_eq!(warned[0].id, upload_ids[0]);
+        assert!(warned[0].expiry_warned_at.is_some());
+
+        let warned_again = UploadRepository::set_expiry_warned_expiring_before(
+            &db_pool,
+            &expiring_before,
+            &window,
+        )
+        .await?;
+        assert!(warned_again.is_empty());
+
+        // Unmarked after a failed email, it is warned on the next pass
+        UploadRepository::unset_expiry_warned(&db_pool, &upload_ids[0]).await?;
+        let warned_again = UploadRepository::set_expiry_warned_expiring_before(
+            &db_pool,
+            &expiring_before,
+            &window,
+        )
+        .await?;
+        assert_eq!(warned_again.len(), 1);
+
+        Ok(())
+    }
+}
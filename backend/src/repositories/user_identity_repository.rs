@@ -0,0 +1,93 @@
+use crate::entities::UserIdentity;
+use sqlx::{Error as SqlxError, PgPool};
+use uuid::Uuid;
+
+pub struct UserIdentityRepository {}
+impl UserIdentityRepository {
+    pub async fn from_issuer_and_subject(
+        db_pool: &PgPool,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<UserIdentity>, SqlxError> {
+        let res: Option<UserIdentity> = sqlx::query_as(
+            "SELECT * FROM user_identities WHERE issuer = $1 AND subject = $2 LIMIT 1;",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn insert(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        issuer: &str,
+        subject: &str,
+        email: Option<&str>,
+    ) -> Result<UserIdentity, SqlxError> {
+        let res: UserIdentity = sqlx::query_as(
+            "INSERT INTO user_identities (user_id, issuer, subject, email) values ($1, $2, $3, $4) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(issuer)
+        .bind(subject)
+        .bind(email)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn set_email(
+        db_pool: &PgPool,
+        id: &Uuid,
+        email: Option<&str>,
+    ) -> Result<(), SqlxError> {
+        sqlx::query("UPDATE user_identities SET updated_at = now(), email = $1 WHERE id = $2;")
+            .bind(email)
+            .bind(id)
+            .execute(db_pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repositories::ReturningId;
+
+    use super::*;
+
+    #[sqlx::test]
+    async fn an_identity_links_a_single_user(db_pool: PgPool) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email) values ('correct') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let other_user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email) values ('other') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let identity =
+            UserIdentityRepository::insert(&db_pool, &user_id, "https://idp", "sub", None).await?;
+
+        let duplicate =
+            UserIdentityRepository::insert(&db_pool, &other_user_id, "https://idp", "sub", None)
+                .await;
+        assert!(matches!(duplicate, Err(SqlxError::Database(e)) if e.is_unique_violation()));
+        let found =
+            UserIdentityRepository::from_issuer_and_subject(&db_pool, "https://idp", "sub").await?;
+        assert!(found.is_some_and(|found| found.id == identity.id && found.user_id == user_id));
+        // Subjects are only unique within their issuer
+        let found =
+            UserIdentityRepository::from_issuer_and_subject(&db_pool, "https://other", "sub")
+                .await?;
+        assert!(found.is_none());
+
+        Ok(())
+    }
+}
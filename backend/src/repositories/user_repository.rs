@@ -1,16 +1,39 @@
-use crate::entities::User;
+use crate::{
+    entities::{Locale, User, UserRole},
+    repositories::ReturningCount,
+};
 use sqlx::{Error as SqlxError, PgPool};
 use uuid::Uuid;
 
 pub struct UserRepository {}
 impl UserRepository {
-    pub async fn list(db_pool: &PgPool) -> Result<Vec<User>, SqlxError> {
-        let res: Vec<User> = sqlx::query_as("SELECT * FROM users;")
+    /// Lists users from the oldest
+    pub async fn list(db_pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<User>, SqlxError> {
+        let res: Vec<User> = sqlx::query_as(
+            "SELECT * FROM users ORDER BY created_at ASC, id ASC LIMIT $1 OFFSET $2;",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Every user from the oldest, only meant for reports over the whole table
+    pub async fn list_all(db_pool: &PgPool) -> Result<Vec<User>, SqlxError> {
+        let res: Vec<User> = sqlx::query_as("SELECT * FROM users ORDER BY created_at ASC, id ASC;")
             .fetch_all(db_pool)
             .await?;
         Ok(res)
     }
 
+    pub async fn count(db_pool: &PgPool) -> Result<i64, SqlxError> {
+        let res: ReturningCount = sqlx::query_as("SELECT COUNT(*) AS count FROM users;")
+            .fetch_one(db_pool)
+            .await?;
+        Ok(res.count)
+    }
+
     pub async fn from_id(db_pool: &PgPool, id: &Uuid) -> Result<Option<User>, SqlxError> {
         let res: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = $1 LIMIT 1;")
             .bind(id)
@@ -19,25 +42,45 @@ impl UserRepository {
         Ok(res)
     }
 
+    /// Emails are compared regardless of case, like the unique index on them
     pub async fn from_email(db_pool: &PgPool, email: &str) -> Result<Option<User>, SqlxError> {
-        let res: Option<User> = sqlx::query_as("SELECT * FROM users WHERE email = $1 LIMIT 1;")
-            .bind(email)
-            .fetch_optional(db_pool)
-            .await?;
+        let res: Option<User> =
+            sqlx::query_as("SELECT * FROM users WHERE lower(email) = lower($1) LIMIT 1;")
+                .bind(email)
+                .fetch_optional(db_pool)
+                .await?;
         Ok(res)
     }
 
     pub async fn create(
         db_pool: &PgPool,
         email: &str,
-        password_hash: &str,
+        password_hash: Option<&str>,
+        locale: Locale,
     ) -> Result<User, SqlxError> {
-        let res: User =
-            sqlx::query_as("INSERT INTO users (email, password_hash) values ($1, $2) RETURNING *;")
-                .bind(email)
-                .bind(password_hash)
-                .fetch_one(db_pool)
-                .await?;
+        let res: User = sqlx::query_as(
+            "INSERT INTO users (email, password_hash, locale) values ($1, $2, $3) RETURNING *;",
+        )
+        .bind(email)
+        .bind(password_hash)
+        .bind(locale)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn set_locale(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        locale: Locale,
+    ) -> Result<User, SqlxError> {
+        let res: User = sqlx::query_as(
+            "UPDATE users SET updated_at = now(), locale = $2 WHERE id = $1 RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(locale)
+        .fetch_one(db_pool)
+        .await?;
         Ok(res)
     }
 
@@ -56,6 +99,24 @@ impl UserRepository {
         Ok(res)
     }
 
+    /// Swaps the email of the user for the one that `verification_id` confirmed
+    pub async fn set_email_and_verification(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        email: &str,
+        verification_id: &Uuid,
+    ) -> Result<Option<User>, SqlxError> {
+        let res: Option<User> = sqlx::query_as(
+            "UPDATE users SET updated_at = now(), email = $1, verified_with_id = $2 WHERE id = $3 RETURNING *;",
+        )
+        .bind(email)
+        .bind(verification_id)
+        .bind(user_id)
+        .fetch_optional(db_pool)
+        .await?;
+        Ok(res)
+    }
+
     pub async fn update_password(
         db_pool: &PgPool,
         user_id: &Uuid,
@@ -71,6 +132,72 @@ impl UserRepository {
         Ok(res)
     }
 
+    pub async fn set_role(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        role: UserRole,
+    ) -> Result<Option<User>, SqlxError> {
+        let res: Option<User> = sqlx::query_as(
+            "UPDATE users SET updated_at = now(), role = $1 WHERE id = $2 RETURNING *;",
+        )
+        .bind(role)
+        .bind(user_id)
+        .fetch_optional(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Disabling keeps the date of the first disable, enabling clears it
+    pub async fn set_disabled(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        disabled: bool,
+    ) -> Result<Option<User>, SqlxError> {
+        let res: Option<User> = sqlx::query_as("UPDATE users SET updated_at = now(), disabled_at = CASE WHEN $1 THEN COALESCE(disabled_at, now()) ELSE NULL END WHERE id = $2 RETURNING *;")
+            .bind(disabled)
+            .bind(user_id)
+            .fetch_optional(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Starts a new 2FA enrollment, or removes 2FA with `None`. Either way 2FA is off until a
+    /// code of the new secret is confirmed.
+    pub async fn set_totp_secret(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        totp_secret: Option<&str>,
+    ) -> Result<User, SqlxError> {
+        let res: User = sqlx::query_as("UPDATE users SET updated_at = now(), totp_secret = $1, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = $2 RETURNING *;")
+            .bind(totp_secret)
+            .bind(user_id)
+            .fetch_one(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn enable_totp(db_pool: &PgPool, user_id: &Uuid) -> Result<User, SqlxError> {
+        let res: User = sqlx::query_as("UPDATE users SET updated_at = now(), totp_enabled_at = now() WHERE id = $1 AND totp_secret IS NOT NULL RETURNING *;")
+            .bind(user_id)
+            .fetch_one(db_pool)
+            .await?;
+        Ok(res)
+    }
+
+    /// Records the time step of an accepted code, `false` when it or a later one was already used
+    pub async fn use_totp_step(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        step: i64,
+    ) -> Result<bool, SqlxError> {
+        let res = sqlx::query("UPDATE users SET totp_last_step = $1 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1);")
+            .bind(step)
+            .bind(user_id)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected() == 1)
+    }
+
     pub async fn delete_from_id(db_pool: &PgPool, id: &Uuid) -> Result<(), SqlxError> {
         sqlx::query("DELETE FROM users WHERE id = $1 RETURNING id;")
             .bind(id)
@@ -82,7 +209,7 @@ impl UserRepository {
 
 #[cfg(test)]
 mod tests {
-    use crate::repositories::{ReturningCount, ReturningId};
+    use crate::repositories::ReturningId;
 
     use super::*;
 
@@ -99,6 +226,21 @@ mod tests {
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn emails_are_unique_regardless_of_case(db_pool: PgPool) -> anyhow::Result<()> {
+        UserRepository::create(&db_pool, "Jane@mail.com", Some("hash"), Locale::En).await?;
+
+        let from_email = UserRepository::from_email(&db_pool, "jane@MAIL.com").await?;
+        assert_eq!(from_email.unwrap().email, "Jane@mail.com");
+        assert!(
+            UserRepository::create(&db_pool, "jane@mail.com", Some("hash"), Locale::En)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn from_email_does_not_contain_incorrect(db_pool: PgPool) -> anyhow::Result<()> {
         sqlx::query("INSERT INTO users (email, password_hash) values ('incorrect', 'hash');")
@@ -120,10 +262,12 @@ mod tests {
         .await?
         .id;
 
-        sqlx::query("INSERT INTO verifications (user_id) values ($1), ($1);")
-            .bind(&user_id)
-            .execute(&db_pool)
-            .await?;
+        sqlx::query(
+            "INSERT INTO verifications (user_id, email, expires_at) values ($1, 'correct', now()), ($1, 'correct', now());",
+        )
+        .bind(user_id)
+        .execute(&db_pool)
+        .await?;
         let count_before: i64 =
             sqlx::query_as::<_, ReturningCount>("SELECT COUNT(*) AS count FROM verifications;")
                 .fetch_one(&db_pool)
@@ -141,4 +285,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test]
+    async fn set_disabled_keeps_first_disable_date(db_pool: PgPool) -> anyhow::Result<()> {
+        let user = UserRepository::create(&db_pool, "correct", Some("hash"), Locale::En).await?;
+        assert!(!user.is_disabled());
+
+        let disabled = UserRepository::set_disabled(&db_pool, &user.id, true)
+            .await?
+            .unwrap();
+        let disabled_again = UserRepository::set_disabled(&db_pool, &user.id, true)
+            .await?
+            .unwrap();
+        assert!(disabled.disabled_at.is_some());
+        assert_eq!(disabled.disabled_at, disabled_again.disabled_at);
+
+        let enabled = UserRepository::set_disabled(&db_pool, &user.id, false)
+            .await?
+            .unwrap();
+        assert!(!enabled.is_disabled());
+
+        Ok(())
+    }
 }
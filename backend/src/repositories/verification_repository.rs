@@ -1,5 +1,5 @@
 use crate::{entities::Verification, repositories::ReturningId};
-use chrono::Utc;
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
 use sqlx::{Error as SqlxError, PgPool};
 use uuid::Uuid;
 
@@ -33,12 +33,66 @@ impl VerificationRepository {
         Ok(res)
     }
 
-    pub async fn insert(db_pool: &PgPool, user_id: &Uuid) -> Result<Verification, SqlxError> {
-        let res: Verification =
-            sqlx::query_as("INSERT INTO verifications (user_id) values ($1) RETURNING *;")
-                .bind(user_id)
-                .fetch_one(db_pool)
-                .await?;
+    /// Unused verifications of the user created after `after`, from the newest
+    pub async fn unused_of_user_id_created_after(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        after: &DateTime<FixedOffset>,
+    ) -> Result<Vec<Verification>, SqlxError> {
+        let res: Vec<Verification> = sqlx::query_as(
+            "SELECT * FROM verifications WHERE user_id = $1 AND activated_at IS NULL AND created_at > $2 ORDER BY created_at DESC;",
+        )
+        .bind(user_id)
+        .bind(after)
+        .fetch_all(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    pub async fn insert(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        email: &str,
+        expires_at: &DateTime<FixedOffset>,
+    ) -> Result<Verification, SqlxError> {
+        let res: Verification = sqlx::query_as(
+            "INSERT INTO verifications (user_id, email, expires_at) values ($1, $2, $3) RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(email)
+        .bind(expires_at)
+        .fetch_one(db_pool)
+        .await?;
+        Ok(res)
+    }
+
+    /// Inserts a verification unless the user got an unused one less than `cooldown` ago, or
+    /// already has `daily_cap` unused ones from the last day. Returns `None` then.
+    pub async fn insert_within_limits(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        email: &str,
+        expires_at: &DateTime<FixedOffset>,
+        cooldown: &TimeDelta,
+        daily_cap: i64,
+    ) -> Result<Option<Verification>, SqlxError> {
+        let mut tx = db_pool.begin().await?;
+        // Verifications of the same user wait for each other, so that they cannot all pass the limits
+        sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE;")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        let res: Option<Verification> = sqlx::query_as(
+            "INSERT INTO verifications (user_id, email, expires_at) SELECT $1, $2, $3 WHERE NOT EXISTS (SELECT 1 FROM verifications WHERE user_id = $1 AND activated_at IS NULL AND created_at > now() - $4) AND (SELECT count(*) FROM verifications WHERE user_id = $1 AND activated_at IS NULL AND created_at > now() - interval '1 day') < $5 RETURNING *;",
+        )
+        .bind(user_id)
+        .bind(email)
+        .bind(expires_at)
+        .bind(cooldown)
+        .bind(daily_cap)
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
         Ok(res)
     }
 
@@ -71,4 +125,52 @@ impl VerificationRepository {
             .await?
             .iter().map(move|returning_id| returning_id.id).collect())
     }
+
+    /// Deletes the unused verifications created before `created_before`
+    pub async fn delete_unused_created_before(
+        db_pool: &PgPool,
+        created_before: &DateTime<FixedOffset>,
+    ) -> Result<u64, SqlxError> {
+        let res = sqlx::query("DELETE FROM verifications WHERE activated_at IS NULL AND created_at < $1 AND id NOT IN (SELECT verified_with_id FROM users WHERE verified_with_id IS NOT NULL);")
+            .bind(created_before)
+            .execute(db_pool)
+            .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[sqlx::test]
+    async fn insert_within_limits_lets_one_of_concurrent_inserts_through(
+        db_pool: PgPool,
+    ) -> anyhow::Result<()> {
+        let user_id: Uuid = sqlx::query_as::<_, ReturningId>(
+            "INSERT INTO users (email, password_hash) values ('correct', 'hash') RETURNING id;",
+        )
+        .fetch_one(&db_pool)
+        .await?
+        .id;
+        let tomorrow = (Utc::now() + TimeDelta::days(1)).fixed_offset();
+        let cooldown = TimeDelta::minutes(2);
+        let insert = || {
+            VerificationRepository::insert_within_limits(
+                &db_pool, &user_id, "correct", &tomorrow, &cooldown, 2,
+            )
+        };
+
+        let (first, second) = tokio::join!(insert(), insert());
+        assert_eq!([first?, second?].iter().flatten().count(), 1);
+
+        // Past the cooldown, the daily cap still holds
+        let age_all = "UPDATE verifications SET created_at = now() - interval '3 minutes';";
+        sqlx::query(age_all).execute(&db_pool).await?;
+        assert!(insert().await?.is_some());
+        sqlx::query(age_all).execute(&db_pool).await?;
+        assert!(insert().await?.is_none());
+
+        Ok(())
+    }
 }
@@ -1,11 +1,23 @@
+pub mod api_token_service;
 pub mod auth_service;
 pub mod discord_service;
 pub mod email_service;
+pub mod jwt_service;
+pub mod login_throttle_service;
+pub mod oidc_service;
+pub mod reaper_service;
+pub mod two_factor_service;
 pub mod upload_service;
 pub mod user_service;
 
+pub use api_token_service::ApiTokenService;
 pub use auth_service::AuthService;
 pub use discord_service::DiscordService;
 pub use email_service::EmailService;
-pub use upload_service::UploadService;
-pub use user_service::UserService;
+pub use jwt_service::{JwtKey, JwtService};
+pub use login_throttle_service::LoginThrottleService;
+pub use oidc_service::OidcService;
+pub use reaper_service::ReaperService;
+pub use two_factor_service::TwoFactorService;
+pub use upload_service::{UploadPolicy, UploadService};
+pub use user_service::{EmailPolicy, PasswordPolicy, UserService};
@@ -0,0 +1,109 @@
+use crate::{
+    entities::{ApiToken, ApiTokenScope, User},
+    repositories::{ApiTokenRepository, UserRepository},
+    state::AppState,
+    utils::{ApiError, context_to_500, hash_token, random_token},
+};
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Personal access tokens, sent as `Authorization: Bearer <token>` like session tokens
+pub struct ApiTokenService {}
+impl ApiTokenService {
+    /// Tells API tokens apart from session JWTs
+    pub const PREFIX: &str = "fsp_";
+
+    pub fn is_api_token(token: &str) -> bool {
+        token.starts_with(Self::PREFIX)
+    }
+
+    /// Creates a token, which is only returned here since just its hash is stored
+    pub async fn create(
+        db_pool: &PgPool,
+        user: &User,
+        name: &str,
+        scopes: &[ApiTokenScope],
+        expires_at: Option<&DateTime<FixedOffset>>,
+    ) -> anyhow::Result<(ApiToken, String)> {
+        let mut scopes = scopes.to_vec();
+        scopes.sort();
+        scopes.dedup();
+        let token = format!("{}{}", Self::PREFIX, random_token());
+        let api_token = ApiTokenRepository::insert(
+            db_pool,
+            &user.id,
+            name.trim(),
+            &hash_token(&token),
+            &scopes,
+            expires_at,
+        )
+        .await
+        .with_context(|| "Failed to insert API token")?;
+        Ok((api_token, token))
+    }
+
+    pub async fn of_user(db_pool: &PgPool, user: &User) -> anyhow::Result<Vec<ApiToken>> {
+        ApiTokenRepository::of_user_id(db_pool, &user.id)
+            .await
+            .with_context(|| "Failed to get API tokens of user")
+    }
+
+    pub async fn delete(db_pool: &PgPool, user: &User, id: &Uuid) -> Result<(), ApiError> {
+        let deleted = ApiTokenRepository::delete_from_id_of_user_id(db_pool, id, &user.id)
+            .await
+            .with_context(|| "Failed to delete API token")
+            .map_err(context_to_500)?;
+        if !deleted {
+            return Err(ApiError::NotFound {
+                code: "api_token_not_found",
+                message: format!("no API token with id {id}"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Owner of `token`, if the token is valid and has `scope`
+    pub async fn get_user(
+        state: &AppState,
+        token: &str,
+        scope: ApiTokenScope,
+    ) -> Result<User, ApiError> {
+        let api_token = ApiTokenRepository::from_token_hash(&state.db_pool, &hash_token(token))
+            .await
+            .with_context(|| "Failed to get API token")
+            .map_err(context_to_500)?
+            .filter(|api_token| !api_token.is_expired())
+            .ok_or(ApiError::Unauthorized {
+                code: "invalid_token",
+                message: "Invalid token".to_string(),
+            })?;
+        if !api_token.has_scope(scope) {
+            return Err(ApiError::Forbidden {
+                code: "missing_scope",
+                message: format!("This token lacks the {} scope", scope.as_str()),
+            });
+        }
+        ApiTokenRepository::set_last_used_at_now(&state.db_pool, &api_token.id)
+            .await
+            .with_context(|| "Failed to update last use of API token")
+            .map_err(context_to_500)?;
+
+        let user = UserRepository::from_id(&state.db_pool, &api_token.user_id)
+            .await
+            .with_context(|| "Failed to get user of API token")
+            .map_err(context_to_500)?
+            .ok_or(ApiError::Unauthorized {
+                code: "invalid_token",
+                message: "Invalid token".to_string(),
+            })?;
+        if user.is_disabled() {
+            return Err(ApiError::Forbidden {
+                code: "account_disabled",
+                message: "This account is disabled".to_string(),
+            });
+        }
+        Ok(user)
+    }
+}
@@ -1,111 +1,448 @@
 use crate::{
-    entities::User,
-    repositories::{UserRepository, VerificationRepository},
-    services::DiscordService,
-    utils::ApiMessage,
+    config::Config,
+    entities::{ApiTokenScope, Session, User},
+    repositories::{SessionRepository, UserRepository, VerificationRepository},
+    services::{
+        ApiTokenService, DiscordService, EmailService, JwtService, LoginThrottleService,
+        UserService,
+    },
+    state::AppState,
+    utils::{ApiError, context_to_500, hash_token, random_token},
 };
-use axum::http::{HeaderMap, StatusCode};
-use chrono::Utc;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use anyhow::{Context, anyhow};
+use axum::http::HeaderMap;
+use chrono::{TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use std::env;
+use sqlx::{Error as SqlxError, PgPool};
+use std::{net::IpAddr, sync::LazyLock};
 use uuid::Uuid;
 
+/// Checked against the password of unknown emails, so that they take as long as existing ones
+static DUMMY_PASSWORD_HASH: LazyLock<String> = LazyLock::new(|| {
+    bcrypt::hash("not-a-password", bcrypt::DEFAULT_COST).expect("Hashing should not fail")
+});
+
+/// Access token and refresh token of a session
+pub struct SessionTokens {
+    pub token: String,
+    pub refresh_token: String,
+}
+
 pub struct AuthService {}
 impl AuthService {
+    /// Access tokens are short-lived, clients get new ones with their refresh token
+    pub const ACCESS_TOKEN_LIFETIME: TimeDelta = TimeDelta::minutes(15);
+    /// A session ends when its refresh token is not used for this long
+    pub const REFRESH_TOKEN_LIFETIME: TimeDelta = TimeDelta::days(30);
+    /// Tolerated difference between our clocks, same as the leeway of `exp`
+    const CLOCK_SKEW: TimeDelta = TimeDelta::seconds(60);
+
+    /// User of the bearer token. API tokens are only accepted with the `scope` of the route, routes
+    /// without one need a session token.
     pub async fn get_user_from_auth_header(
-        db_pool: &PgPool,
+        state: &AppState,
         headers: &HeaderMap,
-    ) -> Result<User, ApiMessage> {
+        scope: Option<ApiTokenScope>,
+    ) -> Result<User, ApiError> {
+        let token = Self::bearer_token(headers)?;
+        if ApiTokenService::is_api_token(token)
+            && let Some(scope) = scope
+        {
+            return ApiTokenService::get_user(state, token, scope).await;
+        }
+        let (user, _) = Self::get_user_and_session_from_auth_header(state, headers).await?;
+        Ok(user)
+    }
+
+    fn bearer_token(headers: &HeaderMap) -> Result<&str, ApiError> {
         let auth_header = headers
             .get("Authorization")
-            .ok_or(ApiMessage {
-                status: StatusCode::UNAUTHORIZED,
+            .ok_or(ApiError::Unauthorized {
+                code: "missing_token",
                 message: "Missing authorization header".to_string(),
             })?
             .to_str()
-            .map_err(|_| ApiMessage {
-                status: StatusCode::UNAUTHORIZED,
+            .map_err(|_| ApiError::Unauthorized {
+                code: "invalid_token",
                 message: "Invalid authorization header".to_string(),
             })?;
-        if !auth_header.starts_with("Bearer ") {
-            return Err(ApiMessage {
-                status: StatusCode::UNAUTHORIZED,
+        auth_header
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .ok_or(ApiError::Unauthorized {
+                code: "invalid_token",
                 message: "Invalid authorization header".to_string(),
+            })
+    }
+
+    /// Rejects tokens whose session was revoked or expired, and API tokens
+    pub async fn get_user_and_session_from_auth_header(
+        state: &AppState,
+        headers: &HeaderMap,
+    ) -> Result<(User, Session), ApiError> {
+        let token = Self::bearer_token(headers)?;
+        if ApiTokenService::is_api_token(token) {
+            return Err(ApiError::Forbidden {
+                code: "session_required",
+                message: "This route needs a login session, API tokens are not accepted"
+                    .to_string(),
             });
         }
-        let token = auth_header.trim_start_matches("Bearer ").trim();
 
-        let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
-        let token_data = jsonwebtoken::decode::<Claims>(
+        let claims = JwtService::decode::<Claims>(
+            &state.config.jwt,
             token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
+            &["exp", "iat", "iss", "aud", "sub"],
         )
-        .map_err(|_| ApiMessage {
-            status: StatusCode::UNAUTHORIZED,
+        .ok()
+        .filter(|claims| {
+            // Tokens from the future were not issued by us, whatever their signature says
+            claims.iat <= (Utc::now() + Self::CLOCK_SKEW).timestamp() as usize
+                && Uuid::parse_str(&claims.jti).is_ok()
+        })
+        .ok_or(ApiError::Unauthorized {
+            code: "invalid_token",
             message: "Invalid token".to_string(),
         })?;
-        let user_id = Uuid::parse_str(&token_data.claims.sub).map_err(|_| ApiMessage {
-            status: StatusCode::UNAUTHORIZED,
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| ApiError::Unauthorized {
+            code: "invalid_token",
             message: "Invalid token subject".to_string(),
         })?;
+        let session_id = Uuid::parse_str(&claims.sid).map_err(|_| ApiError::Unauthorized {
+            code: "invalid_token",
+            message: "Invalid token session".to_string(),
+        })?;
+        let session = SessionRepository::from_id(&state.db_pool, &session_id)
+            .await
+            .with_context(|| "Failed to get session from id")
+            .map_err(context_to_500)?
+            .filter(|session| session.user_id == user_id && session.is_active())
+            .ok_or(ApiError::Unauthorized {
+                code: "session_revoked",
+                message: "Session expired or revoked".to_string(),
+            })?;
 
-        UserRepository::from_id(db_pool, &user_id)
+        let user = UserRepository::from_id(&state.db_pool, &user_id)
             .await
-            .map_err(|_| ApiMessage {
-                status: StatusCode::UNAUTHORIZED,
+            .with_context(|| "Failed to get user of token")
+            .map_err(context_to_500)?
+            .ok_or(ApiError::Unauthorized {
+                code: "invalid_token",
                 message: "Invalid token subject".to_string(),
-            })?
-            .ok_or(ApiMessage {
-                status: StatusCode::UNAUTHORIZED,
-                message: "Server-side error when authorizing token".to_string(),
-            })
+            })?;
+        if user.is_disabled() {
+            return Err(ApiError::Forbidden {
+                code: "account_disabled",
+                message: "This account is disabled".to_string(),
+            });
+        }
+        Ok((user, session))
     }
 
-    pub fn create_jwt_for_user(user: &User) -> anyhow::Result<String> {
-        let secret = env::var("JWT_SECRET").expect("env var JWT_SECRET should be set");
-        let expiration = Utc::now().timestamp() + (60 * 60 * 24 * 7); // 7 days
+    /// Same as `get_user_from_auth_header`, but only lets admins through
+    pub async fn get_admin_from_auth_header(
+        state: &AppState,
+        headers: &HeaderMap,
+    ) -> Result<User, ApiError> {
+        let user = Self::get_user_from_auth_header(state, headers, None).await?;
+        if !user.is_admin() {
+            return Err(ApiError::Forbidden {
+                code: "admin_only",
+                message: "This route is reserved to admins".to_string(),
+            });
+        }
+        Ok(user)
+    }
+
+    fn create_jwt_for_session(config: &Config, session: &Session) -> anyhow::Result<String> {
+        let now = Utc::now();
         let claims = Claims {
-            sub: user.id.to_string(),
-            exp: expiration as usize,
+            sub: session.user_id.to_string(),
+            sid: session.id.to_string(),
+            exp: (now + Self::ACCESS_TOKEN_LIFETIME).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            iss: config.jwt.issuer.clone(),
+            aud: config.jwt.audience.clone(),
+            jti: Uuid::new_v4().to_string(),
         };
-        Ok(jsonwebtoken::encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )?)
+        JwtService::encode(&config.jwt, &claims)
     }
 
-    pub async fn verify(db_pool: &PgPool, verification_id: Uuid) -> anyhow::Result<User> {
-        let verification = VerificationRepository::from_id(db_pool, &verification_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("Verification not found"))?;
-        if verification.activated_at.is_some() {
-            return Err(anyhow::anyhow!("This verification has already been done"));
-        }
-        match verification.user_id {
-            Some(user_id) => {
-                VerificationRepository::set_activated_at_now(db_pool, &verification_id).await?;
-                let user =
-                    UserRepository::set_user_verification(db_pool, &user_id, &verification_id)
-                        .await?
-                        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
-                VerificationRepository::delete_unused_of_user_id(db_pool, &user_id).await?;
-
-                // Notify Discord of email verification
-                let _ = DiscordService::notify_email_verified(&user.email).await;
+    /// Checks the credentials of a login, throttled per account and per client address. Users with
+    /// 2FA still have to pass `TwoFactorService::finish_challenge`.
+    ///
+    /// Every failure gets the same error, so that it does not tell whether the email exists.
+    pub async fn login(
+        state: &AppState,
+        email: &str,
+        password: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<User, ApiError> {
+        let account_key = LoginThrottleService::account_key(&state.config.email_policy, email);
+        let ip_key = client_ip.map(LoginThrottleService::ip_key);
+        let locks_account =
+            LoginThrottleService::reserve(&state.db_pool, &account_key, ip_key.as_deref()).await?;
 
+        let user = UserService::from_email(state, email)
+            .await
+            .with_context(|| "Failed to get user from email")
+            .map_err(context_to_500)?;
+        // Accounts without a password are checked against the dummy hash too, but never match
+        let password_hash = user
+            .as_ref()
+            .and_then(|user| user.password_hash.as_deref())
+            .unwrap_or(DUMMY_PASSWORD_HASH.as_str());
+        let password_matches = bcrypt::verify(password, password_hash)
+            .with_context(|| "Failed to verify password")
+            .map_err(context_to_500)?
+            && user
+                .as_ref()
+                .is_some_and(|user| user.password_hash.is_some());
+
+        match user {
+            Some(user) if password_matches => {
+                // Failures of users with 2FA are only forgotten once their code is checked
+                if user.has_two_factor() || user.is_disabled() {
+                    LoginThrottleService::refund(&state.db_pool, &account_key, ip_key.as_deref())
+                        .await
+                        .map_err(context_to_500)?;
+                } else {
+                    LoginThrottleService::record_success(
+                        &state.db_pool,
+                        &account_key,
+                        ip_key.as_deref(),
+                    )
+                    .await
+                    .map_err(context_to_500)?;
+                }
+                if user.is_disabled() {
+                    return Err(ApiError::Forbidden {
+                        code: "account_disabled",
+                        message: "This account is disabled".to_string(),
+                    });
+                }
                 Ok(user)
             },
-            None => Err(anyhow::anyhow!("No user for that verification")),
+            user => {
+                Self::record_failed_login(
+                    state,
+                    &account_key,
+                    ip_key.as_deref(),
+                    locks_account,
+                    user.as_ref(),
+                )
+                .await?;
+                Err(ApiError::Unauthorized {
+                    code: "invalid_credentials",
+                    message: "Wrong email or password".to_string(),
+                })
+            },
         }
     }
+
+    /// Counts a wrong password or 2FA code, and tells `user` when it locked their account
+    pub async fn record_failed_login(
+        state: &AppState,
+        account_key: &str,
+        ip_key: Option<&str>,
+        locks_account: bool,
+        user: Option<&User>,
+    ) -> Result<(), ApiError> {
+        LoginThrottleService::record_failure(&state.db_pool, account_key, ip_key)
+            .await
+            .map_err(context_to_500)?;
+        if locks_account && let Some(user) = user {
+            // Sent in the background so that the locking attempt is not slower than others, which
+            // would tell that the account exists. The lockout holds anyway, a failed notification
+            // is only logged
+            let state = state.clone();
+            let user = user.clone();
+            tokio::spawn(async move {
+                if let Err(e) = EmailService::send_account_locked_email(&state, &user).await {
+                    println!(
+                        "Failed to notify user {} of account lockout, error: {e:#}",
+                        user.id
+                    );
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Starts a new session, on login
+    pub async fn create_session_for_user(
+        state: &AppState,
+        user: &User,
+    ) -> anyhow::Result<SessionTokens> {
+        let refresh_token = random_token();
+        let session = SessionRepository::insert(
+            &state.db_pool,
+            &user.id,
+            &hash_token(&refresh_token),
+            &(Utc::now() + Self::REFRESH_TOKEN_LIFETIME).fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to create session")?;
+        Ok(SessionTokens {
+            token: Self::create_jwt_for_session(&state.config, &session)?,
+            refresh_token,
+        })
+    }
+
+    /// Trades a refresh token for a new access token and a new refresh token.
+    ///
+    /// Refresh tokens can only be used once, replaying one revokes its whole session.
+    pub async fn refresh_session(
+        state: &AppState,
+        refresh_token: &str,
+    ) -> Result<(User, SessionTokens), ApiError> {
+        let refresh_token_hash = hash_token(refresh_token);
+        let new_refresh_token = random_token();
+        let session = SessionRepository::rotate(
+            &state.db_pool,
+            &refresh_token_hash,
+            &hash_token(&new_refresh_token),
+            &(Utc::now() + Self::REFRESH_TOKEN_LIFETIME).fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to rotate session")
+        .map_err(context_to_500)?;
+        let Some(session) = session else {
+            SessionRepository::revoke_from_previous_refresh_token_hash(
+                &state.db_pool,
+                &refresh_token_hash,
+            )
+            .await
+            .with_context(|| "Failed to revoke replayed session")
+            .map_err(context_to_500)?;
+            return Err(ApiError::Unauthorized {
+                code: "invalid_refresh_token",
+                message: "Invalid refresh token".to_string(),
+            });
+        };
+
+        let user = UserRepository::from_id(&state.db_pool, &session.user_id)
+            .await
+            .with_context(|| "Failed to get user from id")
+            .map_err(context_to_500)?
+            .ok_or(ApiError::Unauthorized {
+                code: "invalid_refresh_token",
+                message: "Invalid refresh token".to_string(),
+            })?;
+        if user.is_disabled() {
+            return Err(ApiError::Forbidden {
+                code: "account_disabled",
+                message: "This account is disabled".to_string(),
+            });
+        }
+        let token = Self::create_jwt_for_session(&state.config, &session)
+            .with_context(|| "Failed to create JWT")
+            .map_err(context_to_500)?;
+        Ok((
+            user,
+            SessionTokens {
+                token,
+                refresh_token: new_refresh_token,
+            },
+        ))
+    }
+
+    pub async fn revoke_session(db_pool: &PgPool, session_id: &Uuid) -> anyhow::Result<()> {
+        SessionRepository::revoke(db_pool, session_id)
+            .await
+            .with_context(|| "Failed to revoke session")
+    }
+
+    /// Logs a user out everywhere, except from `keep_session_id` when there is one
+    pub async fn revoke_sessions_of_user(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        keep_session_id: Option<&Uuid>,
+    ) -> anyhow::Result<u64> {
+        SessionRepository::revoke_of_user_id(db_pool, user_id, keep_session_id)
+            .await
+            .with_context(|| "Failed to revoke sessions")
+    }
+
+    /// Activates the verification, which also swaps the user's email when the verification was
+    /// sent to a new address
+    pub async fn verify(state: &AppState, verification_id: Uuid) -> Result<User, ApiError> {
+        let verification = VerificationRepository::from_id(&state.db_pool, &verification_id)
+            .await
+            .with_context(|| "Failed to get verification")
+            .map_err(context_to_500)?;
+        let Some((verification, user_id)) = verification
+            .and_then(|verification| verification.user_id.map(|user_id| (verification, user_id)))
+        else {
+            return Err(ApiError::NotFound {
+                code: "verification_not_found",
+                message: "This verification link is invalid".to_string(),
+            });
+        };
+        if verification.activated_at.is_some() {
+            return Err(ApiError::Conflict {
+                code: "verification_used",
+                message: "This verification link has already been used".to_string(),
+            });
+        }
+        if verification.is_expired() {
+            return Err(ApiError::Gone {
+                code: "verification_expired",
+                message: "This verification link has expired, ask for a new one".to_string(),
+            });
+        }
+        let user = UserRepository::from_id(&state.db_pool, &user_id)
+            .await
+            .with_context(|| "Failed to get user of verification")
+            .map_err(context_to_500)?
+            .ok_or_else(|| ApiError::internal(anyhow!("User not found")))?;
+
+        let user = if user.email == verification.email {
+            UserRepository::set_user_verification(&state.db_pool, &user_id, &verification_id).await
+        } else {
+            UserRepository::set_email_and_verification(
+                &state.db_pool,
+                &user_id,
+                &verification.email,
+                &verification_id,
+            )
+            .await
+        };
+        let user = match user {
+            Ok(user) => user.ok_or_else(|| ApiError::internal(anyhow!("User not found")))?,
+            Err(SqlxError::Database(e)) if e.is_unique_violation() => {
+                return Err(ApiError::Conflict {
+                    code: "email_taken",
+                    message: "Another account started using this email in the meantime".to_string(),
+                });
+            },
+            Err(e) => return Err(context_to_500(anyhow!(e).context("Failed to verify user"))),
+        };
+        VerificationRepository::set_activated_at_now(&state.db_pool, &verification_id)
+            .await
+            .with_context(|| "Failed to activate verification")
+            .map_err(context_to_500)?;
+        VerificationRepository::delete_unused_of_user_id(&state.db_pool, &user_id)
+            .await
+            .with_context(|| "Failed to delete unused verifications")
+            .map_err(context_to_500)?;
+
+        // Notify Discord of email verification
+        let _ = DiscordService::notify_email_verified(state, &user.email).await;
+
+        Ok(user)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
+    /// Id of the session the token belongs to
+    pub sid: String,
     pub exp: usize,
+    pub iat: usize,
+    pub iss: String,
+    pub aud: String,
+    /// Unique id of the token
+    pub jti: String,
 }
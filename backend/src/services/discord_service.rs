@@ -1,59 +1,28 @@
-use anyhow::Context;
-use reqwest::Client;
-use serde_json::json;
-use std::env;
+use crate::state::AppState;
 
+/// Messages sent to the team through the notifier of the state, a Discord webhook in production
 pub struct DiscordService {}
 impl DiscordService {
-    fn get_webhook_url() -> anyhow::Result<String> {
-        env::var("DISCORD_WEBHOOK_URL").context(
-            "DISCORD_WEBHOOK_URL environment variable not set. Discord notifications will be disabled.",
-        )
-    }
-
-    async fn send_webhook_message(content: &str) -> anyhow::Result<()> {
-        let webhook_url = match Self::get_webhook_url() {
-            Ok(url) => url,
-            Err(_) => {
-                // Discord webhook not configured, silently skip
-                return Ok(());
-            },
-        };
-
-        let client = Client::new();
-        let payload = json!({
-            "content": content
-        });
-
-        client
-            .post(&webhook_url)
-            .json(&payload)
-            .send()
-            .await
-            .context("Failed to send Discord webhook message")?;
-
-        Ok(())
-    }
-
-    pub async fn notify_user_signup(email: &str) -> anyhow::Result<()> {
+    pub async fn notify_user_signup(state: &AppState, email: &str) -> anyhow::Result<()> {
         let message = format!("New user signed up: {}", email);
-        Self::send_webhook_message(&message).await
+        state.notifier.notify(&message).await
     }
 
-    pub async fn notify_email_verified(email: &str) -> anyhow::Result<()> {
+    pub async fn notify_email_verified(state: &AppState, email: &str) -> anyhow::Result<()> {
         let message = format!("Email verified: {}", email);
-        Self::send_webhook_message(&message).await
+        state.notifier.notify(&message).await
     }
 
     pub async fn notify_upload_started(
+        state: &AppState,
         email: &str,
         filename: &str,
-        presigned_get: &str,
+        share_url: &str,
     ) -> anyhow::Result<()> {
         let message = format!(
             "Upload started by {}: {} (`{}`)",
-            email, filename, presigned_get
+            email, filename, share_url
         );
-        Self::send_webhook_message(&message).await
+        state.notifier.notify(&message).await
     }
 }
@@ -1,55 +1,203 @@
-use anyhow::Context;
-use lettre::transport::smtp::SmtpTransport;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, Transport};
-use std::env;
-
-use crate::entities::{User, Verification};
+use crate::email_templates::EmailTemplate;
+use crate::entities::{Upload, User, Verification};
+use crate::mailer::Email;
+use crate::services::{LoginThrottleService, UserService};
+use crate::state::AppState;
 
 pub struct EmailService {}
 impl EmailService {
-    pub fn get_smtp_transport() -> anyhow::Result<SmtpTransport> {
-        let mail_user = env::var("MAIL_USER")?;
-        let mail_password = env::var("MAIL_PASSWORD")?;
-        let creds = Credentials::new(mail_user, mail_password);
-        Ok(if cfg!(debug_assertions) {
-            SmtpTransport::builder_dangerous("localhost")
-                .port(1025)
-                .credentials(creds)
-                .build()
-        } else {
-            SmtpTransport::relay("smtp.gmail.com")?
-                .credentials(creds)
-                .build()
-        })
-    }
-
-    /// Sends a verification email to the user
+    /// Renders `template` in the locale of `user` and sends it to `to`
+    async fn send_template(
+        state: &AppState,
+        user: &User,
+        to: &str,
+        template: EmailTemplate,
+        vars: &[(&str, &str)],
+    ) -> anyhow::Result<()> {
+        let rendered = template.render(
+            state.config.email_templates_dir.as_deref(),
+            user.locale,
+            vars,
+        )?;
+        state
+            .mailer
+            .send(Email {
+                to: to.to_string(),
+                subject: rendered.subject,
+                text: rendered.text,
+                html: rendered.html,
+            })
+            .await
+    }
+
+    /// Sends a verification email to the address of the verification
     pub async fn send_verification_email(
+        state: &AppState,
+        user: &User,
+        verification: &Verification,
+    ) -> anyhow::Result<()> {
+        let web_host = &state.config.web_host;
+        let verification_link = format!("{}/account/verify-email?id={}", web_host, verification.id);
+
+        Self::send_template(
+            state,
+            user,
+            &verification.email,
+            EmailTemplate::Verification,
+            &[
+                ("link", &verification_link),
+                (
+                    "hours",
+                    &UserService::VERIFICATION_LIFETIME.num_hours().to_string(),
+                ),
+            ],
+        )
+        .await
+    }
+
+    /// Sends the single-use password reset link to the user, `token` is never stored in clear
+    pub async fn send_password_reset_email(
+        state: &AppState,
+        user: &User,
+        token: &str,
+    ) -> anyhow::Result<()> {
+        let web_host = &state.config.web_host;
+        let reset_link = format!("{}/account/reset-password?token={}", web_host, token);
+
+        Self::send_template(
+            state,
+            user,
+            &user.email,
+            EmailTemplate::PasswordReset,
+            &[
+                ("link", &reset_link),
+                (
+                    "minutes",
+                    &UserService::PASSWORD_RESET_LIFETIME
+                        .num_minutes()
+                        .to_string(),
+                ),
+            ],
+        )
+        .await
+    }
+
+    /// Sends the link that confirms the new address of the user, `verification` holds that address
+    pub async fn send_email_change_email(
+        state: &AppState,
         user: &User,
         verification: &Verification,
     ) -> anyhow::Result<()> {
-        let web_host = env::var("WEB_HOST")?;
+        let web_host = &state.config.web_host;
         let verification_link = format!("{}/account/verify-email?id={}", web_host, verification.id);
-        let from_email = env::var("MAIL_FROM")?;
-
-        let email = Message::builder()
-            .from(
-                from_email
-                    .parse()
-                    .context("Failed to parse FROM email address")?,
-            )
-            .to(user.email.parse().context("Failed to parse TO email address")?)
-            .subject("Welcome to FileShare - Verify Your Email")
-            .body(format!(
-                "Welcome to FileShare!\n\nPlease verify your email by clicking the link below:\n\n{}\n\nIf you didn't create this account, you can safely ignore this email.",
-                verification_link
-            ))
-            .context("Failed to build email message")?;
-
-        let transport: SmtpTransport = Self::get_smtp_transport()?;
-        transport.send(&email).context("Failed to send email")?;
-
-        Ok(())
+
+        Self::send_template(
+            state,
+            user,
+            &verification.email,
+            EmailTemplate::EmailChange,
+            &[
+                ("link", &verification_link),
+                (
+                    "hours",
+                    &UserService::VERIFICATION_LIFETIME.num_hours().to_string(),
+                ),
+                ("current_email", &user.email),
+            ],
+        )
+        .await
+    }
+
+    /// Warns the current address of the user that a change to `new_email` was asked
+    pub async fn send_email_change_notice_email(
+        state: &AppState,
+        user: &User,
+        new_email: &str,
+    ) -> anyhow::Result<()> {
+        Self::send_template(
+            state,
+            user,
+            &user.email,
+            EmailTemplate::EmailChangeNotice,
+            &[("new_email", new_email)],
+        )
+        .await
+    }
+
+    /// Tells the owner that the share link of `upload` handed out its last allowed download
+    pub async fn send_download_limit_reached_email(
+        state: &AppState,
+        user: &User,
+        upload: &Upload,
+    ) -> anyhow::Result<()> {
+        let web_host = &state.config.web_host;
+        let uploads_link = format!("{}/uploads", web_host);
+
+        Self::send_template(
+            state,
+            user,
+            &user.email,
+            EmailTemplate::DownloadLimitReached,
+            &[
+                ("file_name", &upload.file_name),
+                ("downloads", &upload.downloads.to_string()),
+                ("link", &uploads_link),
+            ],
+        )
+        .await
+    }
+
+    /// Warns the owner that `upload` is about to expire
+    pub async fn send_expiry_warning_email(
+        state: &AppState,
+        user: &User,
+        upload: &Upload,
+    ) -> anyhow::Result<()> {
+        let web_host = &state.config.web_host;
+        let uploads_link = format!("{}/uploads", web_host);
+
+        Self::send_template(
+            state,
+            user,
+            &user.email,
+            EmailTemplate::ExpiryWarning,
+            &[
+                ("file_name", &upload.file_name),
+                (
+                    "expires_at",
+                    &upload
+                        .expires_at
+                        .to_utc()
+                        .format("%Y-%m-%d %H:%M UTC")
+                        .to_string(),
+                ),
+                ("link", &uploads_link),
+            ],
+        )
+        .await
+    }
+
+    /// Tells the user that too many failed logins locked their account for a while
+    pub async fn send_account_locked_email(state: &AppState, user: &User) -> anyhow::Result<()> {
+        let web_host = &state.config.web_host;
+        let reset_link = format!("{}/account/reset-password", web_host);
+
+        Self::send_template(
+            state,
+            user,
+            &user.email,
+            EmailTemplate::AccountLocked,
+            &[
+                (
+                    "minutes",
+                    &LoginThrottleService::ACCOUNT_RULE
+                        .lockout
+                        .num_minutes()
+                        .to_string(),
+                ),
+                ("link", &reset_link),
+            ],
+        )
+        .await
     }
 }
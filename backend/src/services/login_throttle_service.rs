@@ -0,0 +1,260 @@
+use crate::{
+    entities::LoginThrottle,
+    repositories::LoginThrottleRepository,
+    services::EmailPolicy,
+    utils::{ApiError, context_to_500},
+};
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::{PgConnection, PgPool};
+use std::net::IpAddr;
+
+/// How the failed logins of one kind of key are throttled
+pub struct ThrottleRule {
+    /// Failures allowed before every attempt has to wait
+    pub free_attempts: i32,
+    /// Failures that lock the key, counting then starts over
+    pub max_attempts: i32,
+    pub lockout: TimeDelta,
+}
+
+/// Slows down password guessing, per account and per client address.
+///
+/// After the free attempts, every failure doubles the wait before the next attempt, until the key
+/// gets locked. Accounts are keyed by email so that unknown emails behave like existing ones.
+pub struct LoginThrottleService {}
+impl LoginThrottleService {
+    /// Failures are forgotten after this long without any
+    pub const WINDOW: TimeDelta = TimeDelta::hours(1);
+    /// Wait after the last free attempt, doubled by every further failure
+    pub const BASE_BACKOFF: TimeDelta = TimeDelta::seconds(1);
+    pub const MAX_BACKOFF: TimeDelta = TimeDelta::minutes(1);
+    pub const ACCOUNT_RULE: ThrottleRule = ThrottleRule {
+        free_attempts: 3,
+        max_attempts: 10,
+        lockout: TimeDelta::minutes(15),
+    };
+    /// Looser than accounts, since many users can share an address
+    pub const IP_RULE: ThrottleRule = ThrottleRule {
+        free_attempts: 10,
+        max_attempts: 50,
+        lockout: TimeDelta::minutes(15),
+    };
+
+    pub fn account_key(email_policy: &EmailPolicy, email: &str) -> String {
+        let email = email_policy
+            .normalize(email)
+            .unwrap_or_else(|_| email.trim().to_string());
+        format!("email:{}", email.to_lowercase())
+    }
+
+    pub fn ip_key(ip: IpAddr) -> String {
+        format!("ip:{ip}")
+    }
+
+    /// When the next attempt of `throttle` is allowed, in the past when it is allowed right away
+    pub fn retry_at(throttle: &LoginThrottle, rule: &ThrottleRule) -> DateTime<Utc> {
+        let backoff = if throttle.failed_attempts < rule.free_attempts {
+            TimeDelta::zero()
+        } else {
+            let exponent = (throttle.failed_attempts - rule.free_attempts).min(16) as u32;
+            (Self::BASE_BACKOFF * 2i32.pow(exponent)).min(Self::MAX_BACKOFF)
+        };
+        let backoff_until = throttle.last_failed_at.to_utc() + backoff;
+        throttle.locked_until.map_or(backoff_until, |locked_until| {
+            locked_until.to_utc().max(backoff_until)
+        })
+    }
+
+    /// Counts the attempt as failed before the password or code is checked, so that concurrent
+    /// attempts cannot all get through. Refuses it while the account or the address has to wait,
+    /// whatever the password.
+    ///
+    /// Returns whether the attempt locks the account, should it fail. Right attempts have to be
+    /// given back with `record_success` or `refund`.
+    pub async fn reserve(
+        db_pool: &PgPool,
+        account_key: &str,
+        ip_key: Option<&str>,
+    ) -> Result<bool, ApiError> {
+        let mut tx = db_pool
+            .begin()
+            .await
+            .with_context(|| "Failed to begin transaction")
+            .map_err(context_to_500)?;
+        let Some(account) = Self::reserve_key(&mut tx, account_key, &Self::ACCOUNT_RULE).await?
+        else {
+            return Err(Self::too_many_attempts(db_pool, account_key, ip_key).await);
+        };
+        if let Some(ip_key) = ip_key
+            && Self::reserve_key(&mut tx, ip_key, &Self::IP_RULE)
+                .await?
+                .is_none()
+        {
+            return Err(Self::too_many_attempts(db_pool, account_key, Some(ip_key)).await);
+        }
+        tx.commit()
+            .await
+            .with_context(|| "Failed to commit login attempt")
+            .map_err(context_to_500)?;
+        // Only the attempt that starts a lockout resets the counter
+        Ok(account.failed_attempts == 0
+            && account
+                .locked_until
+                .is_some_and(|locked_until| locked_until > Utc::now()))
+    }
+
+    async fn reserve_key(
+        tx: &mut PgConnection,
+        key: &str,
+        rule: &ThrottleRule,
+    ) -> Result<Option<LoginThrottle>, ApiError> {
+        LoginThrottleRepository::reserve_attempt(
+            tx,
+            key,
+            &Self::WINDOW,
+            rule.free_attempts,
+            rule.max_attempts,
+            &Self::BASE_BACKOFF,
+            &Self::MAX_BACKOFF,
+            &rule.lockout,
+        )
+        .await
+        .with_context(|| "Failed to reserve login attempt")
+        .map_err(context_to_500)
+    }
+
+    fn keys(account_key: &str, ip_key: Option<&str>) -> Vec<String> {
+        [Some(account_key), ip_key]
+            .into_iter()
+            .flatten()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The error of a refused attempt, telling when the next one is allowed
+    async fn too_many_attempts(
+        db_pool: &PgPool,
+        account_key: &str,
+        ip_key: Option<&str>,
+    ) -> ApiError {
+        let throttles =
+            match LoginThrottleRepository::from_keys(db_pool, &Self::keys(account_key, ip_key))
+                .await
+                .with_context(|| "Failed to get login throttles")
+            {
+                Ok(throttles) => throttles,
+                Err(e) => return context_to_500(e),
+            };
+        let retry_at = throttles
+            .iter()
+            .map(|throttle| {
+                let rule = if throttle.key == account_key {
+                    &Self::ACCOUNT_RULE
+                } else {
+                    &Self::IP_RULE
+                };
+                Self::retry_at(throttle, rule)
+            })
+            .max()
+            .unwrap_or_else(Utc::now);
+        ApiError::TooManyRequests {
+            code: "too_many_attempts",
+            message: "Too many failed logins, try again later".to_string(),
+            retry_at,
+        }
+    }
+
+    /// Confirms reserved attempts that turned out wrong, the wait before the next one starts now
+    /// rather than when the attempt was reserved
+    pub async fn record_failure(
+        db_pool: &PgPool,
+        account_key: &str,
+        ip_key: Option<&str>,
+    ) -> anyhow::Result<()> {
+        LoginThrottleRepository::touch_from_keys(db_pool, &Self::keys(account_key, ip_key))
+            .await
+            .with_context(|| "Failed to record failed login")
+    }
+
+    /// Forgets the failures of the account and gives the attempt back to the address, which keeps
+    /// its other failures so that logging into an account of its own does not let a client guess
+    /// more passwords of others
+    pub async fn record_success(
+        db_pool: &PgPool,
+        account_key: &str,
+        ip_key: Option<&str>,
+    ) -> anyhow::Result<()> {
+        LoginThrottleRepository::delete_from_key(db_pool, account_key)
+            .await
+            .with_context(|| "Failed to reset failed logins of account")?;
+        if let Some(ip_key) = ip_key {
+            LoginThrottleRepository::refund_attempt(db_pool, ip_key, Self::IP_RULE.max_attempts)
+                .await
+                .with_context(|| "Failed to give back login attempt of address")?;
+        }
+        Ok(())
+    }
+
+    /// Gives back an attempt that was right without finishing the login, keeping the failures
+    pub async fn refund(
+        db_pool: &PgPool,
+        account_key: &str,
+        ip_key: Option<&str>,
+    ) -> anyhow::Result<()> {
+        LoginThrottleRepository::refund_attempt(
+            db_pool,
+            account_key,
+            Self::ACCOUNT_RULE.max_attempts,
+        )
+        .await
+        .with_context(|| "Failed to give back login attempt of account")?;
+        if let Some(ip_key) = ip_key {
+            LoginThrottleRepository::refund_attempt(db_pool, ip_key, Self::IP_RULE.max_attempts)
+                .await
+                .with_context(|| "Failed to give back login attempt of address")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_after_free_attempts_and_is_capped() {
+        let now = Utc::now();
+        let throttle = |failed_attempts| LoginThrottle {
+            key: "email:some@mail.com".to_string(),
+            created_at: now.fixed_offset(),
+            updated_at: now.fixed_offset(),
+            failed_attempts,
+            last_failed_at: now.fixed_offset(),
+            locked_until: None,
+        };
+        let rule = &LoginThrottleService::ACCOUNT_RULE;
+        assert_eq!(LoginThrottleService::retry_at(&throttle(2), rule), now);
+        assert_eq!(
+            LoginThrottleService::retry_at(&throttle(3), rule),
+            now + TimeDelta::seconds(1)
+        );
+        assert_eq!(
+            LoginThrottleService::retry_at(&throttle(5), rule),
+            now + TimeDelta::seconds(4)
+        );
+        assert_eq!(
+            LoginThrottleService::retry_at(&throttle(40), rule),
+            now + LoginThrottleService::MAX_BACKOFF
+        );
+
+        let locked = LoginThrottle {
+            locked_until: Some((now + rule.lockout).fixed_offset()),
+            ..throttle(0)
+        };
+        assert_eq!(
+            LoginThrottleService::retry_at(&locked, rule),
+            now + rule.lockout
+        );
+    }
+}
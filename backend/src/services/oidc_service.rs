@@ -0,0 +1,400 @@
+use crate::{
+    config::OidcProviderConfig,
+    entities::{Locale, User},
+    repositories::{
+        OidcLoginStateRepository, UserIdentityRepository, UserRepository, VerificationRepository,
+    },
+    services::DiscordService,
+    state::AppState,
+    utils::{ApiError, context_to_500, hash_token, random_token},
+};
+use anyhow::{Context, anyhow, bail};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::{TimeDelta, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, jwk::JwkSet};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::Error as SqlxError;
+
+/// Endpoints of a provider, from its discovery document
+#[derive(Debug, Deserialize)]
+pub struct OidcMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Claims of an ID token that logins rely on, signature, issuer, audience and expiry are checked
+/// by `jsonwebtoken`
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+}
+
+/// OpenID Connect logins with the authorization code flow and PKCE.
+///
+/// Accounts of a provider are linked to users by issuer and subject. On their first login, a user
+/// without password is created. Providers with `trust_email` link them to the user with their
+/// email instead, when both sides verified it.
+pub struct OidcService {}
+impl OidcService {
+    /// Time to log in at the provider and come back
+    pub const LOGIN_LIFETIME: TimeDelta = TimeDelta::minutes(10);
+    /// Page of the frontend that providers send users back to, it forwards the code to the API
+    pub const CALLBACK_PATH: &str = "/account/oidc/callback";
+
+    pub fn provider<'a>(
+        state: &'a AppState,
+        provider_id: &str,
+    ) -> Result<&'a OidcProviderConfig, ApiError> {
+        state
+            .config
+            .oidc_providers
+            .iter()
+            .find(|provider| provider.id == provider_id)
+            .ok_or_else(|| ApiError::NotFound {
+                code: "oidc_provider_not_found",
+                message: format!("no identity provider with id {provider_id}"),
+            })
+    }
+
+    pub fn redirect_uri(state: &AppState) -> String {
+        format!("{}{}", state.config.web_host, Self::CALLBACK_PATH)
+    }
+
+    /// S256 challenge of a PKCE verifier (RFC 7636)
+    pub fn code_challenge(code_verifier: &str) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+    }
+
+    pub async fn discover(http: &Client, issuer: &str) -> anyhow::Result<OidcMetadata> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        );
+        let metadata: OidcMetadata = http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        // A discovery document only speaks for its own issuer
+        if metadata.issuer != issuer {
+            bail!(
+                "Discovery document of {issuer} is for issuer {}",
+                metadata.issuer
+            );
+        }
+        Ok(metadata)
+    }
+
+    /// URL of the provider to send the user to, and the secret their browser has to send back with
+    /// the code. Without it, anyone could make a victim finish a login of their own (login CSRF).
+    pub async fn start(state: &AppState, provider_id: &str) -> Result<(String, String), ApiError> {
+        let provider = Self::provider(state, provider_id)?;
+        let metadata = Self::discover(&state.http, &provider.issuer)
+            .await
+            .with_context(|| format!("Failed to discover identity provider {}", provider.id))
+            .map_err(context_to_500)?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .with_context(|| format!("Invalid authorization endpoint of {}", provider.id))
+            .map_err(context_to_500)?;
+
+        let login_state = random_token();
+        let browser_secret = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+        OidcLoginStateRepository::insert(
+            &state.db_pool,
+            &hash_token(&login_state),
+            &hash_token(&browser_secret),
+            &provider.id,
+            &nonce,
+            &code_verifier,
+            &(Utc::now() + Self::LOGIN_LIFETIME).fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to save OIDC login state")
+        .map_err(context_to_500)?;
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &Self::redirect_uri(state))
+            .append_pair("scope", &provider.scopes.join(" "))
+            .append_pair("state", &login_state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &Self::code_challenge(&code_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok((url.to_string(), browser_secret))
+    }
+
+    /// Trades the code the user came back with for their ID token, and gets the user it logs in as.
+    /// Users with 2FA still have to pass `TwoFactorService::finish_challenge`.
+    pub async fn finish(
+        state: &AppState,
+        login_state: &str,
+        browser_secret: &str,
+        code: &str,
+    ) -> Result<User, ApiError> {
+        let login = OidcLoginStateRepository::take_from_state_hash(
+            &state.db_pool,
+            &hash_token(login_state),
+        )
+        .await
+        .with_context(|| "Failed to get OIDC login state")
+        .map_err(context_to_500)?
+        .filter(|login| {
+            !login.is_expired() && login.browser_secret_hash == hash_token(browser_secret)
+        })
+        .ok_or(ApiError::BadRequest {
+            code: "invalid_oidc_state",
+            message: "This login is invalid or expired, start over".to_string(),
+        })?;
+        let provider = Self::provider(state, &login.provider_id)?;
+
+        let claims = Self::id_token_claims(state, provider, code, &login.code_verifier)
+            .await
+            .and_then(|claims| match claims.nonce {
+                Some(ref nonce) if *nonce == login.nonce => Ok(claims),
+                _ => Err(anyhow!("Nonce of the ID token does not match")),
+            })
+            .map_err(|e| {
+                // Not a bug of ours most of the time, the cause is logged for whoever set up the provider
+                println!("OIDC login with {} failed, error: {e:#}", provider.id);
+                ApiError::Unauthorized {
+                    code: "oidc_login_failed",
+                    message: format!("The login with {} could not be verified", provider.name),
+                }
+            })?;
+
+        let user = Self::user_of_identity(state, provider, &claims).await?;
+        if user.is_disabled() {
+            return Err(ApiError::Forbidden {
+                code: "account_disabled",
+                message: "This account is disabled".to_string(),
+            });
+        }
+        Ok(user)
+    }
+
+    async fn id_token_claims(
+        state: &AppState,
+        provider: &OidcProviderConfig,
+        code: &str,
+        code_verifier: &str,
+    ) -> anyhow::Result<IdTokenClaims> {
+        let metadata = Self::discover(&state.http, &provider.issuer)
+            .await
+            .with_context(|| "Failed to discover provider")?;
+
+        let redirect_uri = Self::redirect_uri(state);
+        let mut request = state.http.post(&metadata.token_endpoint).form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &redirect_uri),
+            ("code_verifier", code_verifier),
+            ("client_id", &provider.client_id),
+        ]);
+        if let Some(client_secret) = &provider.client_secret {
+            request = request.basic_auth(&provider.client_id, Some(client_secret));
+        }
+        let tokens: TokenResponse = request
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| "Token endpoint refused the code")?
+            .json()
+            .await?;
+
+        let header = jsonwebtoken::decode_header(&tokens.id_token)?;
+        let key = match header.alg {
+            // Providers sign with the client secret when they do not have keys of their own
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let client_secret = provider.client_secret.as_ref().ok_or_else(|| {
+                    anyhow!("ID token is signed with a client secret, none is set")
+                })?;
+                DecodingKey::from_secret(client_secret.as_bytes())
+            },
+            _ => {
+                let jwks: JwkSet = state
+                    .http
+                    .get(&metadata.jwks_uri)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .with_context(|| "Failed to get keys of the provider")?;
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None if jwks.keys.len() == 1 => jwks.keys.first(),
+                    None => None,
+                }
+                .ok_or_else(|| anyhow!("No key of the provider signed the ID token"))?;
+                DecodingKey::from_jwk(jwk)?
+            },
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&[&provider.issuer]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(&tokens.id_token, &key, &validation)
+            .with_context(|| "Invalid ID token")?
+            .claims;
+        Ok(claims)
+    }
+
+    async fn user_of_identity(
+        state: &AppState,
+        provider: &OidcProviderConfig,
+        claims: &IdTokenClaims,
+    ) -> Result<User, ApiError> {
+        let identity = UserIdentityRepository::from_issuer_and_subject(
+            &state.db_pool,
+            &provider.issuer,
+            &claims.sub,
+        )
+        .await
+        .with_context(|| "Failed to get identity")
+        .map_err(context_to_500)?;
+        if let Some(identity) = identity {
+            if identity.email != claims.email {
+                UserIdentityRepository::set_email(
+                    &state.db_pool,
+                    &identity.id,
+                    claims.email.as_deref(),
+                )
+                .await
+                .with_context(|| "Failed to update email of identity")
+                .map_err(context_to_500)?;
+            }
+            return UserRepository::from_id(&state.db_pool, &identity.user_id)
+                .await
+                .with_context(|| "Failed to get user of identity")
+                .map_err(context_to_500)?
+                .ok_or_else(|| ApiError::internal(anyhow!("User of identity not found")));
+        }
+
+        let email = claims
+            .email
+            .as_deref()
+            .filter(|_| claims.email_verified)
+            .and_then(|email| state.config.email_policy.normalize(email).ok())
+            .ok_or_else(|| ApiError::Forbidden {
+                code: "oidc_email_unverified",
+                message: format!("{} did not share a verified email", provider.name),
+            })?;
+        let existing = UserRepository::from_email(&state.db_pool, &email)
+            .await
+            .with_context(|| "Failed to get user from email")
+            .map_err(context_to_500)?;
+        let user = match existing {
+            // Whoever signed up with the password may not own the email, they must not get the account
+            Some(user) if !user.is_verified() => {
+                return Err(ApiError::Conflict {
+                    code: "account_not_linkable",
+                    message: format!(
+                        "An unverified account already uses this email, verify it before logging in with {}",
+                        provider.name
+                    ),
+                });
+            },
+            Some(user) if provider.trust_email => user,
+            // Anyone who verified the email at the provider would get in without the password
+            Some(_) => {
+                return Err(ApiError::Conflict {
+                    code: "oidc_account_exists",
+                    message: format!(
+                        "An account already uses this email, log in with its password instead of {}",
+                        provider.name
+                    ),
+                });
+            },
+            None => Self::create_user(state, &email).await?,
+        };
+
+        match UserIdentityRepository::insert(
+            &state.db_pool,
+            &user.id,
+            &provider.issuer,
+            &claims.sub,
+            claims.email.as_deref(),
+        )
+        .await
+        {
+            Ok(_) => Ok(user),
+            Err(SqlxError::Database(e)) if e.is_unique_violation() => Err(ApiError::Conflict {
+                code: "oidc_login_in_progress",
+                message: "Another login of this account just linked it, try again".to_string(),
+            }),
+            Err(e) => Err(context_to_500(
+                anyhow!(e).context("Failed to link identity"),
+            )),
+        }
+    }
+
+    /// User without password, verified since the provider vouched for the email
+    async fn create_user(state: &AppState, email: &str) -> Result<User, ApiError> {
+        let user =
+            match UserRepository::create(&state.db_pool, email, None, Locale::default()).await {
+                Ok(user) => user,
+                Err(SqlxError::Database(e)) if e.is_unique_violation() => {
+                    return Err(ApiError::Conflict {
+                        code: "email_taken",
+                        message: "An account already uses this email".to_string(),
+                    });
+                },
+                Err(e) => return Err(context_to_500(anyhow!(e).context("Failed to create user"))),
+            };
+        let verification = VerificationRepository::insert(
+            &state.db_pool,
+            &user.id,
+            email,
+            &Utc::now().fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to insert verification")
+        .map_err(context_to_500)?;
+        VerificationRepository::set_activated_at_now(&state.db_pool, &verification.id)
+            .await
+            .with_context(|| "Failed to activate verification")
+            .map_err(context_to_500)?;
+        let user =
+            UserRepository::set_user_verification(&state.db_pool, &user.id, &verification.id)
+                .await
+                .with_context(|| "Failed to verify user")
+                .map_err(context_to_500)?
+                .ok_or_else(|| ApiError::internal(anyhow!("User not found")))?;
+
+        // Notify Discord of signup
+        let _ = DiscordService::notify_user_signup(state, email).await;
+
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_challenge_follows_rfc_7636() {
+        // Example of RFC 7636, appendix B
+        assert_eq!(
+            OidcService::code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
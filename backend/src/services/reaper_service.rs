@@ -0,0 +1,225 @@
+use crate::{
+    entities::Upload,
+    repositories::{
+        ApiTokenRepository, LoginChallengeRepository, LoginThrottleRepository,
+        OidcLoginStateRepository, SessionRepository, UploadRepository, UserRepository,
+        VerificationRepository,
+    },
+    services::{EmailService, LoginThrottleService, UploadService, UserService},
+    state::AppState,
+};
+use anyhow::Context;
+use aws_sdk_s3::types::{Delete, ObjectIdentifier};
+use chrono::{TimeDelta, Utc};
+use std::{collections::HashSet, fmt, time::Duration};
+use uuid::Uuid;
+
+/// What a single pass of the reaper did
+#[derive(Debug, Default)]
+pub struct ReapSummary {
+    pub abandoned_uploads: u64,
+    pub deleted_uploads: u64,
+    pub failed_objects: usize,
+    pub deleted_sessions: u64,
+    pub deleted_verifications: u64,
+    pub deleted_login_throttles: u64,
+    pub deleted_login_challenges: u64,
+    pub deleted_api_tokens: u64,
+    pub deleted_oidc_login_states: u64,
+    pub expiry_warnings: usize,
+}
+impl fmt::Display for ReapSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} stale uploads abandoned, {} expired uploads deleted, {} objects failed to be deleted, {} inactive sessions deleted, {} unused verifications deleted, {} quiet login throttles deleted, {} expired login challenges deleted, {} expired API tokens deleted, {} expired OIDC logins deleted, {} expiry warnings sent",
+            self.abandoned_uploads,
+            self.deleted_uploads,
+            self.failed_objects,
+            self.deleted_sessions,
+            self.deleted_verifications,
+            self.deleted_login_throttles,
+            self.deleted_login_challenges,
+            self.deleted_api_tokens,
+            self.deleted_oidc_login_states,
+            self.expiry_warnings
+        )
+    }
+}
+
+/// Deletes expired uploads from the bucket and the db, along with inactive sessions.
+///
+/// Every pass is idempotent, and several reapers can run at once since each batch of uploads is
+/// locked with `SKIP LOCKED` until its objects are deleted.
+pub struct ReaperService {}
+impl ReaperService {
+    /// S3 accepts at most 1000 keys per DeleteObjects request
+    const BATCH_SIZE: i64 = 500;
+
+    pub async fn reap(state: &AppState) -> anyhow::Result<ReapSummary> {
+        let mut summary = ReapSummary {
+            abandoned_uploads: UploadRepository::set_abandoned_created_before(
+                &state.db_pool,
+                &(Utc::now() - state.config.reaper.abandon_after).fixed_offset(),
+            )
+            .await
+            .with_context(|| "Failed to mark stale uploads as abandoned")?,
+            deleted_sessions: SessionRepository::delete_inactive(&state.db_pool)
+                .await
+                .with_context(|| "Failed to delete inactive sessions")?,
+            // Kept while they still count towards the daily cap of verification emails
+            deleted_verifications: VerificationRepository::delete_unused_created_before(
+                &state.db_pool,
+                &(Utc::now() - UserService::VERIFICATION_LIFETIME.max(TimeDelta::days(1)))
+                    .fixed_offset(),
+            )
+            .await
+            .with_context(|| "Failed to delete unused verifications")?,
+            deleted_login_throttles: LoginThrottleRepository::delete_quiet_since(
+                &state.db_pool,
+                &(Utc::now() - LoginThrottleService::WINDOW).fixed_offset(),
+            )
+            .await
+            .with_context(|| "Failed to delete quiet login throttles")?,
+            deleted_login_challenges: LoginChallengeRepository::delete_expired(&state.db_pool)
+                .await
+                .with_context(|| "Failed to delete expired login challenges")?,
+            deleted_api_tokens: ApiTokenRepository::delete_expired(&state.db_pool)
+                .await
+                .with_context(|| "Failed to delete expired API tokens")?,
+            deleted_oidc_login_states: OidcLoginStateRepository::delete_expired(&state.db_pool)
+                .await
+                .with_context(|| "Failed to delete expired OIDC logins")?,
+            ..Default::default()
+        };
+        if let Some(window) = state.config.reaper.expiry_warning_window {
+            summary.expiry_warnings = Self::warn_expiring_uploads(state, &window).await?;
+        }
+
+        loop {
+            let (deleted_uploads, failed_objects) = Self::reap_expired_batch(state).await?;
+            summary.deleted_uploads += deleted_uploads;
+            summary.failed_objects += failed_objects;
+            // Stop on a partial batch, or when nothing can be deleted to avoid looping on failures
+            if deleted_uploads == 0 || (deleted_uploads as i64) < Self::BATCH_SIZE {
+                break;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Emails the owners of uploads expiring within `window`, returns how many were sent.
+    ///
+    /// Uploads are marked before the emails go out so that concurrent reapers never warn twice. The
+    /// mark of a failed email is taken back, so that the next pass tries again.
+    async fn warn_expiring_uploads(state: &AppState, window: &TimeDelta) -> anyhow::Result<usize> {
+        let uploads = UploadRepository::set_expiry_warned_expiring_before(
+            &state.db_pool,
+            &(Utc::now() + *window).fixed_offset(),
+            window,
+        )
+        .await
+        .with_context(|| "Failed to mark expiring uploads as warned")?;
+
+        let mut sent = 0;
+        for upload in uploads {
+            match Self::send_expiry_warning(state, &upload).await {
+                Ok(true) => sent += 1,
+                Ok(false) => {},
+                Err(e) => {
+                    println!(
+                        "Failed to send expiry warning of upload {}, error: {e:#}",
+                        upload.id
+                    );
+                    UploadRepository::unset_expiry_warned(&state.db_pool, &upload.id)
+                        .await
+                        .with_context(|| "Failed to unmark upload of failed expiry warning")?;
+                },
+            }
+        }
+        Ok(sent)
+    }
+
+    /// `false` when the upload has no owner to warn anymore
+    async fn send_expiry_warning(state: &AppState, upload: &Upload) -> anyhow::Result<bool> {
+        let Some(user_id) = upload.user_id else {
+            return Ok(false);
+        };
+        let Some(user) = UserRepository::from_id(&state.db_pool, &user_id)
+            .await
+            .with_context(|| "Failed to get owner of upload")?
+        else {
+            return Ok(false);
+        };
+        EmailService::send_expiry_warning_email(state, &user, upload).await?;
+        Ok(true)
+    }
+
+    /// Returns how many uploads were deleted, and how many objects the bucket failed to delete
+    async fn reap_expired_batch(state: &AppState) -> anyhow::Result<(u64, usize)> {
+        let mut tx = state
+            .db_pool
+            .begin()
+            .await
+            .with_context(|| "Failed to begin transaction")?;
+        // Exhausted uploads are kept until the last download URL handed out expired
+        let exhausted_before =
+            (Utc::now() - state.config.upload_policy.get_lifetime).fixed_offset();
+        let uploads = UploadRepository::lock_expired(&mut tx, &exhausted_before, Self::BATCH_SIZE)
+            .await
+            .with_context(|| "Failed to lock expired uploads")?;
+        if uploads.is_empty() {
+            return Ok((0, 0));
+        }
+
+        for upload in &uploads {
+            UploadService::abort_unfinished_multipart(state, upload).await;
+        }
+        let objects = uploads
+            .iter()
+            .map(|upload| ObjectIdentifier::builder().key(upload.object_key()).build())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| "Failed to build object identifiers")?;
+        let output = state
+            .s3
+            .delete_objects()
+            .bucket(&state.config.s3.bucket_name)
+            .delete(
+                Delete::builder()
+                    .set_objects(Some(objects))
+                    .quiet(true)
+                    .build()
+                    .with_context(|| "Failed to build DeleteObjects request")?,
+            )
+            .send()
+            .await
+            .with_context(|| "Failed to delete expired objects in the bucket")?;
+
+        // Keep the rows of objects that could not be deleted, the next pass will retry them
+        let failed_keys: HashSet<&str> = output.errors().iter().filter_map(|e| e.key()).collect();
+        let deleted_ids: Vec<Uuid> = uploads
+            .iter()
+            .filter(|upload| !failed_keys.contains(upload.object_key().as_str()))
+            .map(|upload| upload.id)
+            .collect();
+        let deleted_uploads = UploadRepository::delete_from_ids(&mut tx, &deleted_ids)
+            .await
+            .with_context(|| "Failed to delete expired uploads in the db")?;
+        tx.commit()
+            .await
+            .with_context(|| "Failed to commit transaction")?;
+        Ok((deleted_uploads, failed_keys.len()))
+    }
+
+    /// Runs the reaper forever, for the webserver
+    pub async fn run_periodically(state: AppState, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match Self::reap(&state).await {
+                Ok(summary) => println!("Reaper pass done: {summary}"),
+                Err(e) => println!("Reaper pass failed, error: {e:#}"),
+            }
+        }
+    }
+}
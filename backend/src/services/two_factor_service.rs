@@ -0,0 +1,366 @@
+use crate::{
+    entities::User,
+    repositories::{LoginChallengeRepository, RecoveryCodeRepository, UserRepository},
+    services::{AuthService, LoginThrottleService, UserService},
+    state::AppState,
+    utils::{ApiError, context_to_500, hash_token, random_token},
+};
+use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use sha1::Sha1;
+use sqlx::PgPool;
+use std::net::IpAddr;
+
+/// What the user needs to set up an authenticator app, only shown once
+pub struct TwoFactorEnrollment {
+    /// Base32 secret, for apps that cannot scan `otpauth_uri`
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+/// TOTP second factor (RFC 6238, SHA-1, 6 digits every 30 seconds) with single-use recovery codes
+pub struct TwoFactorService {}
+impl TwoFactorService {
+    /// Shown by authenticator apps next to the email
+    pub const ISSUER: &str = "FileShare";
+    pub const STEP_SECS: i64 = 30;
+    pub const DIGITS: u32 = 6;
+    /// Codes of the previous and next steps are accepted too, for clocks that drift
+    pub const ALLOWED_DRIFT_STEPS: i64 = 1;
+    pub const RECOVERY_CODE_COUNT: usize = 10;
+    /// Time to enter the code after the password step of a login
+    pub const CHALLENGE_LIFETIME: TimeDelta = TimeDelta::minutes(5);
+    /// Wrong codes before the password step has to be done again
+    pub const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+    /// HOTP value (RFC 4226) of `secret` for `counter`
+    fn hotp(secret: &[u8], counter: u64) -> u32 {
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(secret).expect("HMAC should accept keys of any size");
+        mac.update(&counter.to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset],
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]) & 0x7fff_ffff;
+        binary % 10u32.pow(Self::DIGITS)
+    }
+
+    /// Code of `secret` for the time step `step`, `None` when the secret is not valid base32
+    pub fn code_at(secret: &str, step: i64) -> Option<String> {
+        let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+        Some(format!(
+            "{:0width$}",
+            Self::hotp(&secret, step as u64),
+            width = Self::DIGITS as usize
+        ))
+    }
+
+    /// Time step that `code` was generated for around `unix_time`, if it is valid
+    pub fn matching_step(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+        let current_step = unix_time.div_euclid(Self::STEP_SECS);
+        (current_step - Self::ALLOWED_DRIFT_STEPS..=current_step + Self::ALLOWED_DRIFT_STEPS)
+            .find(|&step| Self::code_at(secret, step).is_some_and(|expected| expected == code))
+    }
+
+    fn otpauth_uri(secret: &str, email: &str) -> String {
+        let mut uri = Url::parse("otpauth://totp").expect("URL should be valid");
+        uri.set_path(&format!("/{}:{}", Self::ISSUER, email));
+        uri.query_pairs_mut()
+            .append_pair("secret", secret)
+            .append_pair("issuer", Self::ISSUER)
+            .append_pair("algorithm", "SHA1")
+            .append_pair("digits", &Self::DIGITS.to_string())
+            .append_pair("period", &Self::STEP_SECS.to_string());
+        uri.to_string()
+    }
+
+    /// Recovery codes are typed by hand, so dashes, spaces and case do not matter
+    fn normalize_recovery_code(code: &str) -> String {
+        code.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_lowercase()
+    }
+
+    /// 50 random bits, as `xxxxx-xxxxx`
+    fn generate_recovery_code() -> String {
+        let code = BASE32_NOPAD.encode(&rand::random::<[u8; 7]>())[..10].to_lowercase();
+        format!("{}-{}", &code[..5], &code[5..])
+    }
+
+    fn check_password(user: &User, password: &str) -> Result<(), ApiError> {
+        if UserService::password_matches(user, password).map_err(context_to_500)? {
+            Ok(())
+        } else {
+            Err(ApiError::invalid_field("password", "is wrong"))
+        }
+    }
+
+    /// Starts over any pending enrollment, 2FA only applies once a first code is confirmed
+    pub async fn enroll(
+        db_pool: &PgPool,
+        user: &User,
+        password: &str,
+    ) -> Result<TwoFactorEnrollment, ApiError> {
+        if user.has_two_factor() {
+            return Err(ApiError::Conflict {
+                code: "two_factor_enabled",
+                message: "2FA is already enabled, disable it first".to_string(),
+            });
+        }
+        Self::check_password(user, password)?;
+
+        let secret = BASE32_NOPAD.encode(&rand::random::<[u8; 20]>());
+        let recovery_codes: Vec<String> = (0..Self::RECOVERY_CODE_COUNT)
+            .map(|_| Self::generate_recovery_code())
+            .collect();
+        let code_hashes: Vec<String> = recovery_codes
+            .iter()
+            .map(|code| hash_token(&Self::normalize_recovery_code(code)))
+            .collect();
+        UserRepository::set_totp_secret(db_pool, &user.id, Some(&secret))
+            .await
+            .with_context(|| "Failed to set TOTP secret")
+            .map_err(context_to_500)?;
+        RecoveryCodeRepository::replace_of_user_id(db_pool, &user.id, &code_hashes)
+            .await
+            .with_context(|| "Failed to store recovery codes")
+            .map_err(context_to_500)?;
+
+        Ok(TwoFactorEnrollment {
+            otpauth_uri: Self::otpauth_uri(&secret, &user.email),
+            secret,
+            recovery_codes,
+        })
+    }
+
+    /// Turns 2FA on with a first code of the pending secret
+    pub async fn confirm(db_pool: &PgPool, user: &User, code: &str) -> Result<User, ApiError> {
+        if user.has_two_factor() {
+            return Err(ApiError::Conflict {
+                code: "two_factor_enabled",
+                message: "2FA is already enabled".to_string(),
+            });
+        }
+        let Some(secret) = &user.totp_secret else {
+            return Err(ApiError::Conflict {
+                code: "two_factor_not_enrolled",
+                message: "Start a 2FA enrollment first".to_string(),
+            });
+        };
+        if !Self::use_totp_code(db_pool, user, secret, code)
+            .await
+            .map_err(context_to_500)?
+        {
+            return Err(ApiError::invalid_field("code", "is wrong"));
+        }
+        UserRepository::enable_totp(db_pool, &user.id)
+            .await
+            .with_context(|| "Failed to enable TOTP")
+            .map_err(context_to_500)
+    }
+
+    /// Needs the password and a code, so that a stolen session cannot remove the second factor
+    pub async fn disable(
+        db_pool: &PgPool,
+        user: &User,
+        password: &str,
+        code: &str,
+    ) -> Result<User, ApiError> {
+        if !user.has_two_factor() {
+            return Err(ApiError::Conflict {
+                code: "two_factor_disabled",
+                message: "2FA is not enabled".to_string(),
+            });
+        }
+        Self::check_password(user, password)?;
+        if !Self::use_code(db_pool, user, code)
+            .await
+            .map_err(context_to_500)?
+        {
+            return Err(ApiError::invalid_field("code", "is wrong"));
+        }
+
+        RecoveryCodeRepository::delete_of_user_id(db_pool, &user.id)
+            .await
+            .with_context(|| "Failed to delete recovery codes")
+            .map_err(context_to_500)?;
+        UserRepository::set_totp_secret(db_pool, &user.id, None)
+            .await
+            .with_context(|| "Failed to remove TOTP secret")
+            .map_err(context_to_500)
+    }
+
+    /// Consumes a TOTP code of the current step, that cannot be used again
+    async fn use_totp_code(
+        db_pool: &PgPool,
+        user: &User,
+        secret: &str,
+        code: &str,
+    ) -> anyhow::Result<bool> {
+        let Some(step) = Self::matching_step(secret, code.trim(), Utc::now().timestamp()) else {
+            return Ok(false);
+        };
+        UserRepository::use_totp_step(db_pool, &user.id, step)
+            .await
+            .with_context(|| "Failed to use TOTP step")
+    }
+
+    /// Consumes a TOTP code of an enabled secret, or an unused recovery code
+    pub async fn use_code(db_pool: &PgPool, user: &User, code: &str) -> anyhow::Result<bool> {
+        let (Some(secret), true) = (&user.totp_secret, user.has_two_factor()) else {
+            return Ok(false);
+        };
+        let code = code.trim();
+        if code.len() == Self::DIGITS as usize && code.chars().all(|c| c.is_ascii_digit()) {
+            return Self::use_totp_code(db_pool, user, secret, code).await;
+        }
+        RecoveryCodeRepository::use_code(
+            db_pool,
+            &user.id,
+            &hash_token(&Self::normalize_recovery_code(code)),
+        )
+        .await
+        .with_context(|| "Failed to use recovery code")
+    }
+
+    /// Password step of the login of a user with 2FA, returns the token to send the code with
+    pub async fn start_challenge(
+        db_pool: &PgPool,
+        user: &User,
+    ) -> anyhow::Result<(String, DateTime<Utc>)> {
+        let token = random_token();
+        let expires_at = Utc::now() + Self::CHALLENGE_LIFETIME;
+        LoginChallengeRepository::insert(
+            db_pool,
+            &user.id,
+            &hash_token(&token),
+            &expires_at.fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to create login challenge")?;
+        Ok((token, expires_at))
+    }
+
+    /// Second step of the login, wrong codes count as failed logins of the account
+    pub async fn finish_challenge(
+        state: &AppState,
+        challenge_token: &str,
+        code: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<User, ApiError> {
+        let invalid_challenge = || ApiError::Unauthorized {
+            code: "invalid_challenge",
+            message: "This login expired, log in again".to_string(),
+        };
+        let challenge =
+            LoginChallengeRepository::from_token_hash(&state.db_pool, &hash_token(challenge_token))
+                .await
+                .with_context(|| "Failed to get login challenge")
+                .map_err(context_to_500)?
+                .filter(|challenge| {
+                    challenge.used_at.is_none()
+                        && !challenge.is_expired()
+                        && challenge.failed_attempts < Self::MAX_CHALLENGE_ATTEMPTS
+                })
+                .ok_or_else(invalid_challenge)?;
+        let user = UserRepository::from_id(&state.db_pool, &challenge.user_id)
+            .await
+            .with_context(|| "Failed to get user of login challenge")
+            .map_err(context_to_500)?
+            .ok_or_else(invalid_challenge)?;
+
+        let account_key =
+            LoginThrottleService::account_key(&state.config.email_policy, &user.email);
+        let ip_key = client_ip.map(LoginThrottleService::ip_key);
+        let locks_account =
+            LoginThrottleService::reserve(&state.db_pool, &account_key, ip_key.as_deref()).await?;
+
+        if !Self::use_code(&state.db_pool, &user, code)
+            .await
+            .map_err(context_to_500)?
+        {
+            LoginChallengeRepository::record_failure(&state.db_pool, &challenge.id)
+                .await
+                .with_context(|| "Failed to record wrong code")
+                .map_err(context_to_500)?;
+            AuthService::record_failed_login(
+                state,
+                &account_key,
+                ip_key.as_deref(),
+                locks_account,
+                Some(&user),
+            )
+            .await?;
+            return Err(ApiError::Unauthorized {
+                code: "invalid_code",
+                message: "Wrong code".to_string(),
+            });
+        }
+        if !LoginChallengeRepository::use_from_id(&state.db_pool, &challenge.id)
+            .await
+            .with_context(|| "Failed to use login challenge")
+            .map_err(context_to_500)?
+        {
+            return Err(invalid_challenge());
+        }
+        LoginThrottleService::record_success(&state.db_pool, &account_key, ip_key.as_deref())
+            .await
+            .map_err(context_to_500)?;
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_match_the_rfc_6238_test_vectors() {
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+        // 8-digit values of the RFC, truncated to our 6 digits
+        for (unix_time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (2000000000, "279037"),
+        ] {
+            let step = unix_time / TwoFactorService::STEP_SECS;
+            assert_eq!(
+                TwoFactorService::matching_step(&secret, code, unix_time),
+                Some(step)
+            );
+            // Still accepted from the next step, not two steps later
+            assert_eq!(
+                TwoFactorService::matching_step(&secret, code, unix_time + 30),
+                Some(step)
+            );
+            assert_eq!(
+                TwoFactorService::matching_step(&secret, code, unix_time + 60),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn otpauth_uri_and_recovery_codes_are_well_formed() {
+        let uri = TwoFactorService::otpauth_uri("JBSWY3DPEHPK3PXP", "me+tag@mail.com");
+        assert!(uri.starts_with(
+            "otpauth://totp/FileShare:me+tag@mail.com?secret=JBSWY3DPEHPK3PXP&issuer=FileShare"
+        ));
+
+        let code = TwoFactorService::generate_recovery_code();
+        assert_eq!(code.len(), 11);
+        assert_eq!(
+            TwoFactorService::normalize_recovery_code(&format!(" {} ", code.to_uppercase())),
+            code.replace('-', "")
+        );
+    }
+}
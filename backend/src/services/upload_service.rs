@@ -1,127 +1,830 @@
 use crate::{
-    entities::{Upload, User},
-    repositories::UploadRepository,
-    services::DiscordService,
+    config::{Config, S3Config},
+    dtos::UploadStartRequest,
+    entities::{Upload, UploadPart, UploadStatus, User},
+    repositories::{
+        SortOrder, UploadFilter, UploadPartRepository, UploadRepository, UserRepository,
+    },
+    services::{DiscordService, EmailService},
+    state::AppState,
+    utils::{ApiError, context_to_500, random_token},
 };
 use anyhow::Context;
 use aws_sdk_s3::{
-    Client,
-    config::{Builder, Credentials, Region},
+    error::ProvideErrorMetadata,
     presigning::PresigningConfig,
+    types::{CompletedMultipartUpload, CompletedPart},
 };
+use base64::{Engine, prelude::BASE64_STANDARD};
 use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
 use sqlx::{Error as SqlxError, PgPool};
-use std::{env, time::Duration};
+use std::{collections::BTreeMap, time::Duration};
 use uuid::Uuid;
 
+/// Largest object that S3 accepts in a single PUT, bigger files need a multipart upload
+pub const MAX_SINGLE_PUT_BYTES: i64 = 5 * 1024 * 1024 * 1024;
+/// Largest object that S3 accepts at all, through a multipart upload
+pub const MAX_MULTIPART_BYTES: i64 = 5 * 1024 * 1024 * 1024 * 1024;
+
+/// Wrong share passwords allowed before the upload gets locked
+pub const MAX_SHARE_PASSWORD_ATTEMPTS: i32 = 5;
+/// How long an upload stays locked after too many wrong share passwords
+pub const SHARE_PASSWORD_LOCKOUT: TimeDelta = TimeDelta::minutes(15);
+
+/// Per-deployment limits applied to new uploads
+#[derive(Debug, Clone)]
+pub struct UploadPolicy {
+    /// Lifetime of presigned PUT and POST requests, and of multipart part URLs
+    pub put_lifetime: Duration,
+    /// Lifetime of the presigned GET that share links redirect to
+    pub get_lifetime: Duration,
+    /// Size limit of every upload, single PUTs are also held to `MAX_SINGLE_PUT_BYTES`
+    pub max_bytes: i64,
+    /// Exact content types, or `type/*` prefixes. Empty means that every content type is allowed
+    pub allowed_content_types: Vec<String>,
+}
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self {
+            put_lifetime: Duration::from_secs(900),
+            get_lifetime: Duration::from_secs(300),
+            max_bytes: MAX_MULTIPART_BYTES,
+            allowed_content_types: Vec::new(),
+        }
+    }
+}
+impl UploadPolicy {
+    pub fn allows_content_type(&self, content_type: &str) -> bool {
+        let content_type = content_type.to_lowercase();
+        self.allowed_content_types.is_empty()
+            || self
+                .allowed_content_types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix("/*") {
+                    Some(prefix) => content_type
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('/')),
+                    None => *allowed == content_type,
+                })
+    }
+}
+
+/// Presigned POST form, the bucket checks the policy embedded in `fields` before storing the body
+pub struct PresignedPost {
+    pub url: String,
+    pub fields: BTreeMap<String, String>,
+}
+
 pub struct UploadService {}
 impl UploadService {
-    pub async fn list(db_pool: &PgPool) -> Result<Vec<Upload>, SqlxError> {
-        UploadRepository::list(db_pool).await
+    pub async fn list(
+        db_pool: &PgPool,
+        status: Option<UploadStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Upload>, SqlxError> {
+        UploadRepository::list(db_pool, status, limit, offset).await
+    }
+
+    pub async fn count(db_pool: &PgPool, status: Option<UploadStatus>) -> Result<i64, SqlxError> {
+        UploadRepository::count(db_pool, status).await
     }
 
     pub async fn from_id(db_pool: &PgPool, id: &Uuid) -> Result<Option<Upload>, SqlxError> {
         UploadRepository::from_id(db_pool, id).await
     }
 
-    pub async fn from_user_id(db_pool: &PgPool, user_id: &Uuid) -> Result<Vec<Upload>, SqlxError> {
-        UploadRepository::from_user_id(db_pool, user_id).await
+    pub async fn from_user_id(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        filter: &UploadFilter,
+        order: SortOrder,
+        after: Option<(DateTime<FixedOffset>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<Upload>, SqlxError> {
+        UploadRepository::from_user_id(db_pool, user_id, filter, order, after, limit).await
     }
 
-    pub fn get_s3_client() -> Client {
-        let url = env::var("S3_URL").expect("env var S3_URL should be set");
-        let key = env::var("S3_ACCESS_KEY_ID").expect("env var S3_ACCESS_KEY_ID should be set");
-        let secret =
-            env::var("S3_SECRET_ACCESS_KEY").expect("env var S3_SECRET_ACCESS_KEY should be set");
-        let region = env::var("S3_REGION").expect("env var S3_REGION should be set");
-        let path_style_buckets = env::var("S3_PATH_STYLE_BUCKETS")
-            .expect("env var S3_PATH_STYLE_BUCKETS should be set")
-            == "true";
+    /// Builds a SigV4 presigned POST, which the SDK does not support.
+    ///
+    /// Unlike a presigned PUT, the policy lets the bucket itself reject bodies outside of `content-length-range`.
+    pub fn presign_post(
+        s3: &S3Config,
+        obj_key: &str,
+        content_type: &str,
+        max_size_bytes: i64,
+        expires_in: Duration,
+    ) -> anyhow::Result<PresignedPost> {
+        let S3Config {
+            url,
+            access_key_id: key,
+            secret_access_key: secret,
+            region,
+            path_style_buckets,
+            bucket_name: bucket,
+        } = s3;
 
-        Client::from_conf(
-            Builder::new()
-                .endpoint_url(url)
-                .credentials_provider(Credentials::new(key, secret, None, None, "yes"))
-                .region(Region::new(region))
-                .force_path_style(path_style_buckets)
-                .build(),
-        )
+        let now = Utc::now();
+        let date_stamp = now.format("%Y%m%d").to_string();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let credential = format!("{key}/{date_stamp}/{region}/s3/aws4_request");
+        let expiration = now
+            + TimeDelta::from_std(expires_in)
+                .with_context(|| "Failed to convert expires_in to TimeDelta")?;
+
+        let policy = json!({
+            "expiration": expiration.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+            "conditions": [
+                {"bucket": bucket},
+                {"key": obj_key},
+                {"Content-Type": content_type},
+                ["content-length-range", 0, max_size_bytes],
+                {"x-amz-algorithm": "AWS4-HMAC-SHA256"},
+                {"x-amz-credential": credential},
+                {"x-amz-date": amz_date},
+            ],
+        });
+        let encoded_policy = BASE64_STANDARD.encode(policy.to_string());
+
+        let hmac_sha256 = |key: &[u8], data: &str| -> anyhow::Result<Vec<u8>> {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key)
+                .with_context(|| "Failed to create HMAC from key")?;
+            mac.update(data.as_bytes());
+            Ok(mac.finalize().into_bytes().to_vec())
+        };
+        let date_key = hmac_sha256(format!("AWS4{secret}").as_bytes(), &date_stamp)?;
+        let region_key = hmac_sha256(&date_key, region)?;
+        let service_key = hmac_sha256(&region_key, "s3")?;
+        let signing_key = hmac_sha256(&service_key, "aws4_request")?;
+        let signature = hex::encode(hmac_sha256(&signing_key, &encoded_policy)?);
+
+        let url = url.trim_end_matches('/');
+        let post_url = if *path_style_buckets {
+            format!("{url}/{bucket}")
+        } else {
+            match url.split_once("://") {
+                Some((scheme, host)) => format!("{scheme}://{bucket}.{host}"),
+                None => format!("{bucket}.{url}"),
+            }
+        };
+        let fields = BTreeMap::from([
+            ("key".to_string(), obj_key.to_string()),
+            ("Content-Type".to_string(), content_type.to_string()),
+            (
+                "x-amz-algorithm".to_string(),
+                "AWS4-HMAC-SHA256".to_string(),
+            ),
+            ("x-amz-credential".to_string(), credential),
+            ("x-amz-date".to_string(), amz_date),
+            ("policy".to_string(), encoded_policy),
+            ("x-amz-signature".to_string(), signature),
+        ]);
+        Ok(PresignedPost {
+            url: post_url,
+            fields,
+        })
+    }
+
+    pub async fn from_share_token(
+        db_pool: &PgPool,
+        share_token: &str,
+    ) -> Result<Option<Upload>, SqlxError> {
+        UploadRepository::from_share_token(db_pool, share_token).await
     }
 
-    pub fn get_bucket_name() -> String {
-        env::var("S3_BUCKET_NAME").expect("env var S3_BUCKET_NAME should be set")
+    /// Public link to an upload, it goes through the backend to get a fresh download URL
+    pub fn get_share_url(config: &Config, upload: &Upload) -> String {
+        format!("{}/s/{}", config.web_host, upload.share_token)
     }
 
-    pub async fn register_new_upload_and_generate_presigned_put(
+    /// Checks the password of a protected upload, wrong guesses are counted and lock the upload for a while
+    pub async fn unlock_share(
         db_pool: &PgPool,
-        user: User,
-        file_name: String,
-        content_type: String,
-        expires_at: DateTime<FixedOffset>,
-    ) -> anyhow::Result<String> {
-        let client = Self::get_s3_client();
-        let id = Uuid::new_v4();
-        let obj_key = format!("content/{}/{}", id, file_name);
-        let presigned_get_response = client
+        upload: &Upload,
+        password: &str,
+    ) -> Result<(), ApiError> {
+        let Some(password_hash) = &upload.password_hash else {
+            return Ok(());
+        };
+        // The attempt is counted as wrong first, so that concurrent guesses cannot skip the lock
+        let claimed = UploadRepository::claim_password_attempt(
+            db_pool,
+            &upload.id,
+            MAX_SHARE_PASSWORD_ATTEMPTS,
+            &SHARE_PASSWORD_LOCKOUT,
+        )
+        .await
+        .with_context(|| "Failed to claim password attempt")
+        .map_err(context_to_500)?;
+        if claimed.is_none() {
+            let locked_until = UploadRepository::from_id(db_pool, &upload.id)
+                .await
+                .with_context(|| "Failed to get locked upload")
+                .map_err(context_to_500)?
+                .and_then(|upload| upload.password_locked_until)
+                .map_or_else(Utc::now, |locked_until| locked_until.to_utc());
+            return Err(ApiError::TooManyRequests {
+                code: "share_locked",
+                message: "Too many wrong passwords, try again later".to_string(),
+                retry_at: locked_until,
+            });
+        }
+
+        if bcrypt::verify(password, password_hash)
+            .with_context(|| "Failed to verify password")
+            .map_err(context_to_500)?
+        {
+            UploadRepository::reset_password_failures(db_pool, &upload.id)
+                .await
+                .with_context(|| "Failed to reset password failures")
+                .map_err(context_to_500)?;
+            Ok(())
+        } else {
+            Err(ApiError::Unauthorized {
+                code: "wrong_password",
+                message: "Wrong password".to_string(),
+            })
+        }
+    }
+
+    /// Counts a download of a shareable upload, and refuses it once the download limit is reached.
+    ///
+    /// An exhausted upload that should be deleted is deleted here once the last download URL handed out expired.
+    pub async fn record_download(state: &AppState, upload: Upload) -> Result<Upload, ApiError> {
+        if let Some(upload_db) = UploadRepository::increment_downloads(&state.db_pool, &upload.id)
+            .await
+            .with_context(|| "Failed to increment downloads")
+            .map_err(context_to_500)?
+        {
+            if upload_db.max_downloads == Some(upload_db.downloads) {
+                // Sent in the background so that the download does not wait for the mail server,
+                // it went through anyway so a failed notification is only logged
+                let state = state.clone();
+                let upload = upload_db.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = Self::notify_download_limit_reached(&state, &upload).await {
+                        println!(
+                            "Failed to notify owner of exhausted upload {}, error: {e:#}",
+                            upload.id
+                        );
+                    }
+                });
+            }
+            return Ok(upload_db);
+        }
+
+        let last_download_expired = upload.last_downloaded_at.is_none_or(|last_downloaded_at| {
+            last_downloaded_at + state.config.upload_policy.get_lifetime < Utc::now()
+        });
+        if upload.delete_when_exhausted && last_download_expired {
+            Self::delete_upload(state, upload)
+                .await
+                .with_context(|| "Failed to delete exhausted upload")
+                .map_err(context_to_500)?;
+        }
+        Err(ApiError::Gone {
+            code: "download_limit_reached",
+            message: "This link reached its download limit".to_string(),
+        })
+    }
+
+    /// Emails the owner of `upload` that its share link handed out its last allowed download
+    async fn notify_download_limit_reached(
+        state: &AppState,
+        upload: &Upload,
+    ) -> anyhow::Result<()> {
+        let Some(user_id) = upload.user_id else {
+            return Ok(());
+        };
+        let Some(user) = UserRepository::from_id(&state.db_pool, &user_id)
+            .await
+            .with_context(|| "Failed to get owner of upload")?
+        else {
+            return Ok(());
+        };
+        EmailService::send_download_limit_reached_email(state, &user, upload).await
+    }
+
+    /// Presigns a short-lived GET for a shareable upload, it never outlives the upload itself
+    pub async fn presign_download(state: &AppState, upload: &Upload) -> anyhow::Result<String> {
+        let until_expiration = TimeDelta::to_std(&(upload.expires_at - Utc::now().fixed_offset()))
+            .with_context(|| "Upload is already expired")?;
+        let expires_in = state
+            .config
+            .upload_policy
+            .get_lifetime
+            .min(until_expiration);
+        let presigned_get_response = state
+            .s3
             .get_object()
-            .bucket(Self::get_bucket_name())
-            .key(&obj_key)
+            .bucket(&state.config.s3.bucket_name)
+            .key(upload.object_key())
             .presigned(
-                PresigningConfig::expires_in(
-                    TimeDelta::to_std(&(expires_at - Utc::now().fixed_offset()))
-                        .with_context(|| "Failed to convert expires_at to expires_in")?,
-                )
-                .with_context(|| "Failed to convert Duration to PresigningConfig")?,
+                PresigningConfig::expires_in(expires_in)
+                    .with_context(|| "Failed to convert Duration to PresigningConfig")?,
             )
             .await
             .with_context(|| "Failed to presign get request")?;
-        let presigned_get_url = String::from(presigned_get_response.uri());
+        Ok(String::from(presigned_get_response.uri()))
+    }
 
-        UploadRepository::insert(
-            db_pool,
-            &id,
+    /// Inserts a new pending upload, its optional password is hashed like user passwords
+    async fn insert_new_upload(
+        state: &AppState,
+        user: &User,
+        id: &Uuid,
+        request: &UploadStartRequest,
+        multipart_upload_id: Option<&str>,
+    ) -> anyhow::Result<Upload> {
+        let password_hash = request
+            .password
+            .as_ref()
+            .map(|password| bcrypt::hash(password, bcrypt::DEFAULT_COST))
+            .transpose()
+            .with_context(|| "Failed to hash password")?;
+        let upload = UploadRepository::insert(
+            &state.db_pool,
+            id,
             &user.id,
-            &file_name,
-            &content_type,
-            &presigned_get_url,
-            &expires_at,
+            &request.file_name,
+            &request.content_type,
+            &request.expires_at,
+            multipart_upload_id,
+            &random_token(),
+            password_hash.as_deref(),
+            request.max_downloads,
+            request.delete_when_exhausted,
         )
         .await
         .with_context(|| "Failed to create new upload in db")?;
 
         // Notify Discord of upload started
-        let _ = DiscordService::notify_upload_started(&user.email, &file_name, &presigned_get_url)
-            .await;
+        let _ = DiscordService::notify_upload_started(
+            state,
+            &user.email,
+            &upload.file_name,
+            &Self::get_share_url(&state.config, &upload),
+        )
+        .await;
+
+        Ok(upload)
+    }
+
+    /// Registers a pending upload and presigns both a PUT request and a POST form for it.
+    ///
+    /// The request must already be validated against the [`UploadPolicy`].
+    pub async fn register_new_upload_and_generate_presigned_put(
+        state: &AppState,
+        user: User,
+        request: UploadStartRequest,
+    ) -> anyhow::Result<(Upload, String, PresignedPost)> {
+        let policy = &state.config.upload_policy;
+        let id = Uuid::new_v4();
+        let upload = Self::insert_new_upload(state, &user, &id, &request, None).await?;
+        let obj_key = upload.object_key();
 
-        Ok(String::from(
-            client
+        let presigned_put_url = String::from(
+            state
+                .s3
                 .put_object()
-                .bucket(Self::get_bucket_name())
+                .bucket(&state.config.s3.bucket_name)
                 .key(&obj_key)
-                .content_type(content_type)
+                .content_type(&request.content_type)
+                .content_length(request.size_bytes)
                 .presigned(
-                    PresigningConfig::expires_in(Duration::from_secs(30))
+                    PresigningConfig::expires_in(policy.put_lifetime)
                         .with_context(|| "Failed to convert Duration to PresigningConfig")?,
                 )
                 .await
                 .with_context(|| "Failed to presign put request")?
                 .uri(),
-        ))
+        );
+        let presigned_post = Self::presign_post(
+            &state.config.s3,
+            &obj_key,
+            &request.content_type,
+            request.size_bytes,
+            policy.put_lifetime,
+        )?;
+        Ok((upload, presigned_put_url, presigned_post))
     }
 
-    pub async fn delete_upload(db_pool: &PgPool, upload: Upload) -> anyhow::Result<()> {
-        let client = Self::get_s3_client();
-        let obj_key = format!("content/{}/{}", upload.id, upload.file_name);
-        client
+    /// Starts a multipart upload in the bucket, part URLs are presigned later on demand
+    pub async fn register_new_multipart_upload(
+        state: &AppState,
+        user: User,
+        request: UploadStartRequest,
+    ) -> anyhow::Result<Upload> {
+        let id = Uuid::new_v4();
+        let obj_key = Upload::object_key_of(&id, &request.file_name);
+
+        let multipart_upload = state
+            .s3
+            .create_multipart_upload()
+            .bucket(&state.config.s3.bucket_name)
+            .key(&obj_key)
+            .content_type(&request.content_type)
+            .send()
+            .await
+            .with_context(|| "Failed to create multipart upload in the bucket")?;
+        let multipart_upload_id = multipart_upload
+            .upload_id()
+            .ok_or_else(|| anyhow::anyhow!("Bucket did not return a multipart upload id"))?;
+
+        Self::insert_new_upload(state, &user, &id, &request, Some(multipart_upload_id)).await
+    }
+
+    fn ensure_pending_multipart(upload: &Upload) -> Result<&str, ApiError> {
+        let multipart_upload_id =
+            upload
+                .multipart_upload_id
+                .as_deref()
+                .ok_or(ApiError::Conflict {
+                    code: "not_multipart",
+                    message: "This upload is not a multipart upload".to_string(),
+                })?;
+        if upload.status != UploadStatus::Pending {
+            return Err(ApiError::Conflict {
+                code: "upload_not_pending",
+                message: "This multipart upload is not pending anymore".to_string(),
+            });
+        }
+        Ok(multipart_upload_id)
+    }
+
+    fn ensure_valid_part_number(part_number: i32) -> Result<(), ApiError> {
+        if !(1..=10000).contains(&part_number) {
+            return Err(ApiError::invalid_field(
+                "part_number",
+                "must be between 1 and 10000",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Presigns one PUT URL per requested part, returned in the same order as `part_numbers`
+    pub async fn presign_upload_parts(
+        state: &AppState,
+        upload: &Upload,
+        part_numbers: &[i32],
+    ) -> Result<Vec<(i32, String)>, ApiError> {
+        let multipart_upload_id = Self::ensure_pending_multipart(upload)?;
+        for part_number in part_numbers {
+            Self::ensure_valid_part_number(*part_number)?;
+        }
+
+        let policy = &state.config.upload_policy;
+        let mut presigned_parts = Vec::with_capacity(part_numbers.len());
+        for part_number in part_numbers {
+            let presigned_part = state
+                .s3
+                .upload_part()
+                .bucket(&state.config.s3.bucket_name)
+                .key(upload.object_key())
+                .upload_id(multipart_upload_id)
+                .part_number(*part_number)
+                .presigned(
+                    PresigningConfig::expires_in(policy.put_lifetime)
+                        .with_context(|| "Failed to convert Duration to PresigningConfig")
+                        .map_err(context_to_500)?,
+                )
+                .await
+                .with_context(|| "Failed to presign upload part request")
+                .map_err(context_to_500)?;
+            presigned_parts.push((*part_number, String::from(presigned_part.uri())));
+        }
+        Ok(presigned_parts)
+    }
+
+    /// Stores the progress of a multipart upload so that the client can resume it later
+    pub async fn record_upload_part(
+        db_pool: &PgPool,
+        upload: &Upload,
+        part_number: i32,
+        etag: &str,
+        size_bytes: Option<i64>,
+    ) -> Result<UploadPart, ApiError> {
+        Self::ensure_pending_multipart(upload)?;
+        Self::ensure_valid_part_number(part_number)?;
+        UploadPartRepository::upsert(
+            db_pool,
+            &upload.id,
+            part_number,
+            etag.trim_matches('"'),
+            size_bytes,
+        )
+        .await
+        .with_context(|| "Failed to record upload part in db")
+        .map_err(context_to_500)
+    }
+
+    pub async fn list_upload_parts(
+        db_pool: &PgPool,
+        upload_id: &Uuid,
+    ) -> Result<Vec<UploadPart>, SqlxError> {
+        UploadPartRepository::from_upload_id(db_pool, upload_id).await
+    }
+
+    /// Assembles the parts of a multipart upload, then verifies the resulting object like a single PUT upload.
+    ///
+    /// When `parts` is `None`, the parts recorded in the db are used.
+    pub async fn complete_multipart_upload(
+        state: &AppState,
+        upload: Upload,
+        parts: Option<Vec<(i32, String)>>,
+        expected_size_bytes: Option<i64>,
+    ) -> Result<Upload, ApiError> {
+        let multipart_upload_id = Self::ensure_pending_multipart(&upload)?.to_string();
+        let mut parts = match parts {
+            Some(parts) => parts,
+            None => Self::list_upload_parts(&state.db_pool, &upload.id)
+                .await
+                .with_context(|| "Failed to get upload parts from db")
+                .map_err(context_to_500)?
+                .into_iter()
+                .map(|part| (part.part_number, part.etag))
+                .collect(),
+        };
+        if parts.is_empty() {
+            return Err(ApiError::invalid_field("parts", "must not be empty"));
+        }
+        for (part_number, _) in &parts {
+            Self::ensure_valid_part_number(*part_number)?;
+        }
+        parts.sort_by_key(|(part_number, _)| *part_number);
+
+        let completed_parts = parts
+            .into_iter()
+            .map(|(part_number, etag)| {
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .e_tag(format!("\"{}\"", etag.trim_matches('"')))
+                    .build()
+            })
+            .collect();
+        match state
+            .s3
+            .complete_multipart_upload()
+            .bucket(&state.config.s3.bucket_name)
+            .key(upload.object_key())
+            .upload_id(&multipart_upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await
+        {
+            Ok(_) => {},
+            Err(e) if e.as_service_error().is_some() => {
+                return Err(ApiError::Unprocessable {
+                    code: "parts_rejected",
+                    message: format!(
+                        "The bucket refused to assemble the parts: {}",
+                        e.code().unwrap_or("unknown error")
+                    ),
+                });
+            },
+            Err(e) => {
+                return Err(context_to_500(
+                    anyhow::Error::new(e).context("Failed to complete multipart upload"),
+                ));
+            },
+        }
+
+        Self::verify_and_complete(state, upload, expected_size_bytes, None).await
+    }
+
+    /// Aborts a pending multipart upload, which frees the parts already stored in the bucket
+    pub async fn abort_multipart_upload(
+        state: &AppState,
+        upload: Upload,
+    ) -> Result<Upload, ApiError> {
+        let multipart_upload_id = Self::ensure_pending_multipart(&upload)?;
+        state
+            .s3
+            .abort_multipart_upload()
+            .bucket(&state.config.s3.bucket_name)
+            .key(upload.object_key())
+            .upload_id(multipart_upload_id)
+            .send()
+            .await
+            .with_context(|| "Failed to abort multipart upload in the bucket")
+            .map_err(context_to_500)?;
+        UploadPartRepository::delete_of_upload_id(&state.db_pool, &upload.id)
+            .await
+            .with_context(|| "Failed to delete upload parts in the db")
+            .map_err(context_to_500)?;
+        UploadRepository::set_abandoned(&state.db_pool, &upload.id)
+            .await
+            .with_context(|| "Failed to mark upload as abandoned in the db")
+            .map_err(context_to_500)?
+            .ok_or_else(|| ApiError::Conflict {
+                code: "upload_not_pending",
+                message: "This multipart upload is not pending anymore".to_string(),
+            })
+    }
+
+    /// Checks that the object of a pending upload reached the bucket, then records what was stored.
+    ///
+    /// If the stored object does not match what was announced, the object is deleted and the upload is marked as failed.
+    pub async fn complete_upload(
+        state: &AppState,
+        upload: Upload,
+        expected_size_bytes: Option<i64>,
+        expected_etag: Option<String>,
+    ) -> Result<Upload, ApiError> {
+        if upload.status != UploadStatus::Pending {
+            return Err(ApiError::Conflict {
+                code: "upload_not_pending",
+                message: "Only pending uploads can be completed".to_string(),
+            });
+        }
+        if upload.is_multipart() {
+            return Err(ApiError::Conflict {
+                code: "multipart_upload",
+                message: "Multipart uploads are completed with their parts".to_string(),
+            });
+        }
+
+        Self::verify_and_complete(state, upload, expected_size_bytes, expected_etag).await
+    }
+
+    async fn verify_and_complete(
+        state: &AppState,
+        upload: Upload,
+        expected_size_bytes: Option<i64>,
+        expected_etag: Option<String>,
+    ) -> Result<Upload, ApiError> {
+        let head = match state
+            .s3
+            .head_object()
+            .bucket(&state.config.s3.bucket_name)
+            .key(upload.object_key())
+            .send()
+            .await
+        {
+            Ok(head) => head,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => {
+                return Err(ApiError::Conflict {
+                    code: "file_missing",
+                    message: "The file has not been uploaded yet".to_string(),
+                });
+            },
+            Err(e) => {
+                return Err(context_to_500(
+                    anyhow::Error::new(e).context("Failed to head upload in the bucket"),
+                ));
+            },
+        };
+
+        let size_bytes = head.content_length().unwrap_or_default();
+        let etag = head
+            .e_tag()
+            .unwrap_or_default()
+            .trim_matches('"')
+            .to_string();
+        let content_type = head
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+
+        let mut mismatches = Vec::new();
+        if content_type != upload.content_type {
+            mismatches.push(format!(
+                "content type is {content_type} instead of {}",
+                upload.content_type
+            ));
+        }
+        let max_bytes = state.config.upload_policy.max_bytes;
+        if size_bytes > max_bytes {
+            mismatches.push(format!(
+                "size is {size_bytes} bytes, over the limit of {max_bytes}"
+            ));
+        }
+        if let Some(expected_size_bytes) = expected_size_bytes
+            && expected_size_bytes != size_bytes
+        {
+            mismatches.push(format!(
+                "size is {size_bytes} bytes instead of {expected_size_bytes}"
+            ));
+        }
+        if let Some(expected_etag) = expected_etag
+            && expected_etag.trim_matches('"') != etag
+        {
+            mismatches.push(format!("etag is {etag} instead of {expected_etag}"));
+        }
+        if !mismatches.is_empty() {
+            state
+                .s3
+                .delete_object()
+                .bucket(&state.config.s3.bucket_name)
+                .key(upload.object_key())
+                .send()
+                .await
+                .with_context(|| "Failed to delete mismatching upload in the bucket")
+                .map_err(context_to_500)?;
+            UploadRepository::set_failed(&state.db_pool, &upload.id)
+                .await
+                .with_context(|| "Failed to mark upload as failed in the db")
+                .map_err(context_to_500)?;
+            return Err(ApiError::Unprocessable {
+                code: "file_mismatch",
+                message: format!("Uploaded file does not match: {}", mismatches.join(", ")),
+            });
+        }
+
+        UploadRepository::set_completed(
+            &state.db_pool,
+            &upload.id,
+            size_bytes,
+            &etag,
+            &content_type,
+        )
+        .await
+        .with_context(|| "Failed to mark upload as completed in the db")
+        .map_err(context_to_500)?
+        .ok_or_else(|| ApiError::Conflict {
+            code: "upload_not_pending",
+            message: "Only pending uploads can be completed".to_string(),
+        })
+    }
+
+    /// Parts of an unfinished multipart upload are only freed by aborting it, errors are ignored
+    /// since the multipart upload may already be gone from the bucket
+    pub async fn abort_unfinished_multipart(state: &AppState, upload: &Upload) {
+        if let Some(multipart_upload_id) = &upload.multipart_upload_id
+            && !upload.is_completed()
+        {
+            let _ = state
+                .s3
+                .abort_multipart_upload()
+                .bucket(&state.config.s3.bucket_name)
+                .key(upload.object_key())
+                .upload_id(multipart_upload_id)
+                .send()
+                .await;
+        }
+    }
+
+    pub async fn delete_upload(state: &AppState, upload: Upload) -> anyhow::Result<()> {
+        Self::abort_unfinished_multipart(state, &upload).await;
+        state
+            .s3
             .delete_object()
-            .bucket(Self::get_bucket_name())
-            .key(obj_key)
+            .bucket(&state.config.s3.bucket_name)
+            .key(upload.object_key())
             .send()
             .await
             .with_context(|| "Failed to delete upload in the bucket")?;
-        UploadRepository::delete_from_id(db_pool, &upload.id)
+        UploadRepository::delete_from_id(&state.db_pool, &upload.id)
             .await
             .with_context(|| "Failed to delete upload in the db")?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_policy_matches_content_type_prefixes() {
+        let policy = UploadPolicy {
+            put_lifetime: Duration::from_secs(60),
+            get_lifetime: Duration::from_secs(60),
+            max_bytes: 1024,
+            allowed_content_types: vec!["image/*".to_string(), "application/pdf".to_string()],
+        };
+        assert!(policy.allows_content_type("image/png"));
+        assert!(policy.allows_content_type("Application/PDF"));
+        assert!(!policy.allows_content_type("imagery/png"));
+        assert!(!policy.allows_content_type("text/plain"));
+
+        let open_policy = UploadPolicy {
+            allowed_content_types: Vec::new(),
+            ..policy
+        };
+        assert!(open_policy.allows_content_type("text/plain"));
+    }
+
+    #[test]
+    fn default_policy_only_holds_single_puts_to_their_limit() {
+        let request = UploadStartRequest {
+            file_name: "file.bin".to_string(),
+            expires_at: (Utc::now() + TimeDelta::days(1)).fixed_offset(),
+            content_type: "application/octet-stream".to_string(),
+            size_bytes: MAX_SINGLE_PUT_BYTES + 1,
+            password: None,
+            max_downloads: None,
+            delete_when_exhausted: false,
+        };
+        let policy = UploadPolicy::default();
+        assert!(request.validate(&policy, false).is_err());
+        assert!(request.validate(&policy, true).is_ok());
+    }
+}
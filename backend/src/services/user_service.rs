@@ -1,77 +1,694 @@
 use crate::{
-    entities::User,
-    repositories::{UserRepository, VerificationRepository},
-    services::{DiscordService, EmailService},
+    entities::{Locale, User, Verification},
+    repositories::{
+        ApiTokenRepository, PasswordResetRepository, UploadRepository, UserRepository,
+        VerificationRepository,
+    },
+    services::{AuthService, DiscordService, EmailService, UploadService},
+    state::AppState,
+    utils::{ApiError, FieldErrors, context_to_500, hash_token, random_token},
 };
-use anyhow::Context;
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, TimeDelta, Utc};
 use sqlx::{Error as SqlxError, PgPool};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::PathBuf,
+    sync::Arc,
+};
 use uuid::Uuid;
 
+/// Passwords from public breaches bundled with the app, `BREACHED_PASSWORDS_PATH` replaces them
+const BUNDLED_BREACHED_PASSWORDS: &str = include_str!("../../assets/breached-passwords.txt");
+
+/// Rules that every new password must follow
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    /// Lowercased passwords from public breaches, shared since the list can be big
+    breached_passwords: Arc<HashSet<String>>,
+    /// File the list was read from, `None` for the bundled list
+    pub breached_passwords_file: Option<PathBuf>,
+}
+impl fmt::Debug for PasswordPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasswordPolicy")
+            .field("breached_passwords", &self.breached_passwords.len())
+            .field("breached_passwords_file", &self.breached_passwords_file)
+            .finish()
+    }
+}
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::from_breached_list(BUNDLED_BREACHED_PASSWORDS, None)
+    }
+}
+impl PasswordPolicy {
+    pub const MIN_CHARS: usize = 8;
+    /// bcrypt ignores everything after the first 72 bytes
+    pub const MAX_BYTES: usize = 72;
+
+    /// One password per line, blank lines and `#` comments are skipped
+    pub fn from_breached_list(list: &str, file: Option<PathBuf>) -> Self {
+        Self {
+            breached_passwords: Arc::new(
+                list.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_lowercase)
+                    .collect(),
+            ),
+            breached_passwords_file: file,
+        }
+    }
+
+    /// Lists what is wrong with a password, empty when it is acceptable
+    pub fn check(&self, password: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        if password.chars().count() < Self::MIN_CHARS {
+            problems.push(format!("must be at least {} characters", Self::MIN_CHARS));
+        }
+        if password.len() > Self::MAX_BYTES {
+            problems.push(format!("must not be over {} bytes", Self::MAX_BYTES));
+        }
+        if self.breached_passwords.contains(&password.to_lowercase()) {
+            problems.push("is too common, it appears in known data breaches".to_string());
+        }
+        problems
+    }
+}
+
+/// How email addresses are checked and normalized before being stored
+#[derive(Debug, Clone, Default)]
+pub struct EmailPolicy {
+    /// Drops the `+tag` of local parts, so that `me+tag@x.com` and `me@x.com` are one account
+    pub strip_plus_tag: bool,
+}
+impl EmailPolicy {
+    /// Trims the address and lowercases its domain, the local part keeps its case since some
+    /// servers care about it. Accounts are still unique regardless of case.
+    pub fn normalize(&self, email: &str) -> Result<String, String> {
+        let email = email.trim();
+        if email.len() > 254 {
+            return Err("must not be over 254 characters".to_string());
+        }
+        let Some((local, domain)) = email.rsplit_once('@') else {
+            return Err("must contain an @".to_string());
+        };
+
+        let local = match local.split_once('+') {
+            Some((untagged, _)) if self.strip_plus_tag => untagged,
+            _ => local,
+        };
+        let local_is_valid = !local.is_empty()
+            && local.len() <= 64
+            && local.split('.').all(|atom| {
+                !atom.is_empty()
+                    && atom
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+            });
+        if !local_is_valid {
+            return Err("has an invalid part before the @".to_string());
+        }
+
+        let domain = domain.to_ascii_lowercase();
+        let labels: Vec<&str> = domain.split('.').collect();
+        let domain_is_valid = labels.len() >= 2
+            && labels.iter().all(|label| {
+                (1..=63).contains(&label.len())
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            });
+        if !domain_is_valid {
+            return Err("has an invalid domain".to_string());
+        }
+
+        Ok(format!("{local}@{domain}"))
+    }
+
+    /// Form to look up stored addresses with. Only trimmed with its domain lowercased, so that
+    /// accounts stored before the policy changed, with a tag or an address it refuses, are found.
+    pub fn lookup_form(email: &str) -> String {
+        let email = email.trim();
+        match email.rsplit_once('@') {
+            Some((local, domain)) => format!("{local}@{}", domain.to_lowercase()),
+            None => email.to_string(),
+        }
+    }
+}
+
+/// Users whose addresses are the same account under the email policy
+#[derive(Debug)]
+pub struct EmailDuplicates {
+    pub normalized_email: String,
+    pub users: Vec<User>,
+}
+
+/// Existing rows that do not follow the email policy, see `fileshare-email-report`
+#[derive(Debug, Default)]
+pub struct EmailReport {
+    pub duplicates: Vec<EmailDuplicates>,
+    /// Users with an address that the policy refuses, along with the problem
+    pub invalid: Vec<(User, String)>,
+}
+
 pub struct UserService {}
 impl UserService {
-    pub async fn list(db_pool: &PgPool) -> Result<Vec<User>, SqlxError> {
-        UserRepository::list(db_pool).await
+    pub const PASSWORD_RESET_LIFETIME: TimeDelta = TimeDelta::hours(1);
+    /// Asking again before this does not send another email
+    pub const PASSWORD_RESET_COOLDOWN: TimeDelta = TimeDelta::minutes(2);
+    pub const VERIFICATION_LIFETIME: TimeDelta = TimeDelta::days(1);
+    /// Minimum delay between two verification emails of a user
+    pub const VERIFICATION_RESEND_COOLDOWN: TimeDelta = TimeDelta::minutes(2);
+    /// Verification emails a user can be sent over a day, email changes included
+    pub const VERIFICATION_DAILY_CAP: usize = 5;
+
+    pub async fn list(db_pool: &PgPool, limit: i64, offset: i64) -> Result<Vec<User>, SqlxError> {
+        UserRepository::list(db_pool, limit, offset).await
+    }
+
+    pub async fn count(db_pool: &PgPool) -> Result<i64, SqlxError> {
+        UserRepository::count(db_pool).await
     }
 
     pub async fn from_id(db_pool: &PgPool, id: &Uuid) -> Result<Option<User>, SqlxError> {
         UserRepository::from_id(db_pool, id).await
     }
 
-    pub async fn from_email(db_pool: &PgPool, email: &str) -> Result<Option<User>, SqlxError> {
-        UserRepository::from_email(db_pool, email).await
+    /// Finds the account of `email` under the current email policy, like signup stores it, then
+    /// as it was stored for accounts created before the policy
+    pub async fn from_email(state: &AppState, email: &str) -> Result<Option<User>, SqlxError> {
+        let lookup_email = EmailPolicy::lookup_form(email);
+        if let Ok(normalized) = state.config.email_policy.normalize(email)
+            && normalized != lookup_email
+            && let Some(user) = UserRepository::from_email(&state.db_pool, &normalized).await?
+        {
+            return Ok(Some(user));
+        }
+        UserRepository::from_email(&state.db_pool, &lookup_email).await
     }
 
-    pub async fn signup(db_pool: &PgPool, email: &str, password: &str) -> anyhow::Result<User> {
-        let user = UserRepository::create(
-            db_pool,
-            email,
-            &bcrypt::hash(password, bcrypt::DEFAULT_COST)
-                .with_context(|| "Failed to hash password")?,
+    pub async fn signup(
+        state: &AppState,
+        email: &str,
+        password: &str,
+        locale: Locale,
+    ) -> Result<User, ApiError> {
+        let mut field_errors = FieldErrors::default();
+        let email = state
+            .config
+            .email_policy
+            .normalize(email)
+            .unwrap_or_else(|problem| {
+                field_errors.add("email", vec![problem]);
+                String::new()
+            });
+        field_errors.add("password", state.config.password_policy.check(password));
+        field_errors.into_result()?;
+
+        let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .with_context(|| "Failed to hash password")
+            .map_err(context_to_500)?;
+        let user = match UserRepository::create(
+            &state.db_pool,
+            &email,
+            Some(&password_hash),
+            locale,
         )
-        .await?;
+        .await
+        {
+            Ok(user) => user,
+            Err(SqlxError::Database(e)) if e.is_unique_violation() => {
+                return Err(ApiError::Conflict {
+                    code: "email_taken",
+                    message: "An account already uses this email".to_string(),
+                });
+            },
+            Err(e) => return Err(context_to_500(anyhow!(e).context("Failed to create user"))),
+        };
 
-        Self::start_email_verification_process(db_pool, &user).await?;
+        Self::start_email_verification_process(state, &user).await?;
 
         // Notify Discord of signup
-        let _ = DiscordService::notify_user_signup(&email).await;
+        let _ = DiscordService::notify_user_signup(state, &email).await;
 
         Ok(user)
     }
 
-    pub async fn start_email_verification_process(
+    /// When the user can be sent another verification email, in the past if they can right away.
+    ///
+    /// Only unused verifications count, following a link lifts the limits.
+    pub async fn next_verification_at(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+    ) -> anyhow::Result<DateTime<Utc>> {
+        let now = Utc::now();
+        let recent = VerificationRepository::unused_of_user_id_created_after(
+            db_pool,
+            user_id,
+            &(now - TimeDelta::days(1)).fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to get recent verifications")?;
+
+        let mut next = now;
+        if let Some(last) = recent.first() {
+            next = next.max(last.created_at.to_utc() + Self::VERIFICATION_RESEND_COOLDOWN);
+        }
+        // The cap allows another email once the oldest of the last allowed ones is a day old
+        if let Some(oldest_counted) = recent.get(Self::VERIFICATION_DAILY_CAP - 1) {
+            next = next.max(oldest_counted.created_at.to_utc() + TimeDelta::days(1));
+        }
+        Ok(next)
+    }
+
+    /// Creates a verification of `email` and returns when the next one will be allowed, or refuses
+    /// while the user is over the resend limits
+    async fn create_verification(
         db_pool: &PgPool,
         user: &User,
-    ) -> anyhow::Result<()> {
+        email: &str,
+    ) -> Result<(Verification, DateTime<Utc>), ApiError> {
+        let Some(verification) = VerificationRepository::insert_within_limits(
+            db_pool,
+            &user.id,
+            email,
+            &(Utc::now() + Self::VERIFICATION_LIFETIME).fixed_offset(),
+            &Self::VERIFICATION_RESEND_COOLDOWN,
+            Self::VERIFICATION_DAILY_CAP as i64,
+        )
+        .await
+        .with_context(|| "Failed to create verification")
+        .map_err(context_to_500)?
+        else {
+            let next_verification_at = Self::next_verification_at(db_pool, &user.id)
+                .await
+                .map_err(context_to_500)?;
+            return Err(ApiError::TooManyRequests {
+                code: "verification_rate_limited",
+                message: "Too many verification emails, try again later".to_string(),
+                retry_at: next_verification_at,
+            });
+        };
+        let next_verification_at = Self::next_verification_at(db_pool, &user.id)
+            .await
+            .map_err(context_to_500)?;
+        Ok((verification, next_verification_at))
+    }
+
+    /// Returns when the user can ask for another verification email
+    pub async fn start_email_verification_process(
+        state: &AppState,
+        user: &User,
+    ) -> Result<DateTime<Utc>, ApiError> {
         if user.is_verified() {
-            return Err(anyhow::anyhow!(
-                "User is already verified, refusing to start verification process"
-            ));
+            return Err(ApiError::Conflict {
+                code: "already_verified",
+                message: "User is already verified, refusing to send verification email"
+                    .to_string(),
+            });
         }
 
-        let verification = VerificationRepository::insert(db_pool, &user.id).await?;
+        let (verification, next_verification_at) =
+            Self::create_verification(&state.db_pool, user, &user.email).await?;
 
         // Send verification email
-        EmailService::send_verification_email(user, &verification)
+        EmailService::send_verification_email(state, user, &verification)
             .await
             .with_context(|| "Failed to send verification email")
+            .map_err(context_to_500)?;
+        Ok(next_verification_at)
+    }
+
+    /// Always `false` for accounts without a password, which only log in through an identity provider
+    pub fn password_matches(user: &User, password: &str) -> anyhow::Result<bool> {
+        match &user.password_hash {
+            Some(password_hash) => {
+                bcrypt::verify(password, password_hash).with_context(|| "Failed to verify password")
+            },
+            None => Ok(false),
+        }
+    }
+
+    /// Sends a confirmation link to the new address and a notice to the current one, the email of
+    /// the user only changes once the link is followed
+    pub async fn start_email_change(
+        state: &AppState,
+        user: &User,
+        current_password: &str,
+        new_email: &str,
+    ) -> Result<(), ApiError> {
+        let mut field_errors = FieldErrors::default();
+        if !Self::password_matches(user, current_password).map_err(context_to_500)? {
+            field_errors.add("current_password", vec!["is wrong".to_string()]);
+        }
+        let new_email = match state.config.email_policy.normalize(new_email) {
+            Ok(new_email) if new_email.eq_ignore_ascii_case(&user.email) => {
+                field_errors.add("email", vec!["is already your email".to_string()]);
+                new_email
+            },
+            Ok(new_email) => new_email,
+            Err(problem) => {
+                field_errors.add("email", vec![problem]);
+                String::new()
+            },
+        };
+        field_errors.into_result()?;
+
+        if UserRepository::from_email(&state.db_pool, &new_email)
+            .await
+            .with_context(|| "Failed to get user from email")
+            .map_err(context_to_500)?
+            .is_some()
+        {
+            return Err(ApiError::Conflict {
+                code: "email_taken",
+                message: "An account already uses this email".to_string(),
+            });
+        }
+
+        let (verification, _) = Self::create_verification(&state.db_pool, user, &new_email).await?;
+        EmailService::send_email_change_email(state, user, &verification)
+            .await
+            .with_context(|| "Failed to send email change confirmation")
+            .map_err(context_to_500)?;
+        EmailService::send_email_change_notice_email(state, user, &new_email)
+            .await
+            .with_context(|| "Failed to send email change notice")
+            .map_err(context_to_500)?;
+        Ok(())
     }
 
+    /// Emails sent to the user from now on are in `locale`
+    pub async fn set_locale(db_pool: &PgPool, user: &User, locale: Locale) -> anyhow::Result<User> {
+        UserRepository::set_locale(db_pool, &user.id, locale)
+            .await
+            .with_context(|| "Failed to set locale")
+    }
+
+    /// Needs the current password of the user. Other sessions and the API tokens of the user are
+    /// revoked, only `current_session_id` stays logged in
     pub async fn change_password(
-        db_pool: &PgPool,
-        user_id: &Uuid,
-        new_password: String,
-    ) -> anyhow::Result<User> {
+        state: &AppState,
+        user: &User,
+        current_password: &str,
+        new_password: &str,
+        current_session_id: &Uuid,
+    ) -> Result<User, ApiError> {
+        // A session alone does not prove who holds it, accounts of an identity provider set their
+        // first password from a password reset link
+        if user.password_hash.is_none() {
+            return Err(ApiError::Forbidden {
+                code: "password_reset_required",
+                message: "Set a first password from a password reset link".to_string(),
+            });
+        }
+        let mut field_errors = FieldErrors::default();
+        if !Self::password_matches(user, current_password).map_err(context_to_500)? {
+            field_errors.add("current_password", vec!["is wrong".to_string()]);
+        }
+        field_errors.add("password", state.config.password_policy.check(new_password));
+        field_errors.into_result()?;
+
+        let db_pool = &state.db_pool;
         let password_hash = bcrypt::hash(new_password, bcrypt::DEFAULT_COST)
-            .with_context(|| "Failed to hash password")?;
-        let user = UserRepository::update_password(db_pool, user_id, &password_hash)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+            .with_context(|| "Failed to hash password")
+            .map_err(context_to_500)?;
+        let user = UserRepository::update_password(db_pool, &user.id, &password_hash)
+            .await
+            .with_context(|| "Failed to update password")
+            .map_err(context_to_500)?
+            .ok_or_else(|| ApiError::internal(anyhow!("User not found")))?;
+        AuthService::revoke_sessions_of_user(db_pool, &user.id, Some(current_session_id))
+            .await
+            .map_err(context_to_500)?;
+        ApiTokenRepository::delete_of_user_id(db_pool, &user.id)
+            .await
+            .with_context(|| "Failed to delete API tokens")
+            .map_err(context_to_500)?;
         Ok(user)
     }
 
+    /// Emails a password reset link, unknown and disabled accounts are silently ignored to not leak them,
+    /// like users who asked again within the cooldown
+    pub async fn start_password_reset(state: &AppState, email: &str) -> anyhow::Result<()> {
+        let Some(user) = Self::from_email(state, email)
+            .await
+            .with_context(|| "Failed to get user from email")?
+            .filter(|user| !user.is_disabled())
+        else {
+            return Ok(());
+        };
+
+        let token = random_token();
+        if PasswordResetRepository::insert_unless_created_within(
+            &state.db_pool,
+            &user.id,
+            &hash_token(&token),
+            &(Utc::now() + Self::PASSWORD_RESET_LIFETIME).fixed_offset(),
+            &Self::PASSWORD_RESET_COOLDOWN,
+        )
+        .await
+        .with_context(|| "Failed to create password reset")?
+        .is_none()
+        {
+            return Ok(());
+        }
+        EmailService::send_password_reset_email(state, &user, &token)
+            .await
+            .with_context(|| "Failed to send password reset email")
+    }
+
+    /// Sets the new password and logs the user out everywhere, API tokens included. Returns `None`
+    /// if the token is not usable
+    pub async fn finish_password_reset(
+        state: &AppState,
+        token: &str,
+        new_password: &str,
+    ) -> Result<Option<User>, ApiError> {
+        // Checked first, so that a refused password does not burn the token
+        let mut field_errors = FieldErrors::default();
+        field_errors.add("password", state.config.password_policy.check(new_password));
+        field_errors.into_result()?;
+
+        let db_pool = &state.db_pool;
+        let Some(password_reset) =
+            PasswordResetRepository::use_from_token_hash(db_pool, &hash_token(token))
+                .await
+                .with_context(|| "Failed to use password reset")
+                .map_err(context_to_500)?
+        else {
+            return Ok(None);
+        };
+
+        let password_hash = bcrypt::hash(new_password, bcrypt::DEFAULT_COST)
+            .with_context(|| "Failed to hash password")
+            .map_err(context_to_500)?;
+        let user =
+            UserRepository::update_password(db_pool, &password_reset.user_id, &password_hash)
+                .await
+                .with_context(|| "Failed to update password")
+                .map_err(context_to_500)?
+                .ok_or_else(|| ApiError::internal(anyhow!("User not found")))?;
+        PasswordResetRepository::delete_unused_of_user_id(db_pool, &user.id)
+            .await
+            .with_context(|| "Failed to delete unused password resets")
+            .map_err(context_to_500)?;
+        AuthService::revoke_sessions_of_user(db_pool, &user.id, None)
+            .await
+            .map_err(context_to_500)?;
+        ApiTokenRepository::delete_of_user_id(db_pool, &user.id)
+            .await
+            .with_context(|| "Failed to delete API tokens")
+            .map_err(context_to_500)?;
+        Ok(Some(user))
+    }
+
+    /// Lists the existing users that break the email policy, duplicates compare addresses
+    /// regardless of case like the unique index does
+    pub async fn email_report(
+        db_pool: &PgPool,
+        policy: &EmailPolicy,
+    ) -> anyhow::Result<EmailReport> {
+        let users = UserRepository::list_all(db_pool)
+            .await
+            .with_context(|| "Failed to list users")?;
+
+        let mut report = EmailReport::default();
+        let mut by_email: BTreeMap<String, Vec<User>> = BTreeMap::new();
+        for user in users {
+            match policy.normalize(&user.email) {
+                Ok(email) => by_email.entry(email.to_lowercase()).or_default().push(user),
+                Err(problem) => report.invalid.push((user, problem)),
+            }
+        }
+        report.duplicates = by_email
+            .into_iter()
+            .filter(|(_, users)| users.len() > 1)
+            .map(|(normalized_email, users)| EmailDuplicates {
+                normalized_email,
+                users,
+            })
+            .collect();
+        Ok(report)
+    }
+
     pub async fn delete_user(db_pool: &PgPool, user_id: &Uuid) -> anyhow::Result<()> {
         UserRepository::delete_from_id(db_pool, user_id)
             .await
             .with_context(|| "Failed to delete user")
     }
+
+    /// Verifies a user without an email round-trip, for admins
+    pub async fn force_verify(state: &AppState, user: &User) -> Result<User, ApiError> {
+        if user.is_verified() {
+            return Err(ApiError::Conflict {
+                code: "already_verified",
+                message: "User is already verified".to_string(),
+            });
+        }
+        // Admins are not held by the resend limits
+        let verification = VerificationRepository::insert(
+            &state.db_pool,
+            &user.id,
+            &user.email,
+            &(Utc::now() + Self::VERIFICATION_LIFETIME).fixed_offset(),
+        )
+        .await
+        .with_context(|| "Failed to create verification")
+        .map_err(context_to_500)?;
+        AuthService::verify(state, verification.id).await
+    }
+
+    pub async fn set_disabled(
+        db_pool: &PgPool,
+        user_id: &Uuid,
+        disabled: bool,
+    ) -> anyhow::Result<User> {
+        UserRepository::set_disabled(db_pool, user_id, disabled)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("User not found"))
+    }
+
+    /// Deletes a user along with every upload they made, instead of leaving them ownerless
+    pub async fn delete_user_and_uploads(state: &AppState, user_id: &Uuid) -> anyhow::Result<()> {
+        let uploads = UploadRepository::list_of_user_id(&state.db_pool, user_id)
+            .await
+            .with_context(|| "Failed to list uploads of user")?;
+        for upload in uploads {
+            UploadService::delete_upload(state, upload).await?;
+        }
+        Self::delete_user(&state.db_pool, user_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_policy_refuses_short_long_and_breached_passwords() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.check("correct horse battery staple").is_empty());
+        assert_eq!(policy.check("short").len(), 1);
+        assert_eq!(policy.check(&"a".repeat(73)).len(), 1);
+        assert_eq!(policy.check("PassWord123").len(), 1);
+        // Multi-byte characters count once for the length, but fully for bcrypt
+        assert!(policy.check(&"é".repeat(8)).is_empty());
+        assert_eq!(policy.check(&"é".repeat(37)).len(), 1);
+    }
+
+    #[test]
+    fn email_policy_normalizes_domain_and_refuses_invalid_addresses() {
+        let policy = EmailPolicy::default();
+        assert_eq!(
+            policy.normalize("  Jane.Doe+news@Mail.COM ").unwrap(),
+            "Jane.Doe+news@mail.com"
+        );
+        for invalid in [
+            "",
+            "jane",
+            "@mail.com",
+            "jane@",
+            "jane@mail",
+            "jane..doe@mail.com",
+            "jane doe@mail.com",
+            "jane@-mail.com",
+            "jane@mail..com",
+        ] {
+            assert!(
+                policy.normalize(invalid).is_err(),
+                "{invalid} should be refused"
+            );
+        }
+
+        let policy = EmailPolicy {
+            strip_plus_tag: true,
+        };
+        assert_eq!(
+            policy.normalize("jane+news@mail.com").unwrap(),
+            "jane@mail.com"
+        );
+        assert!(policy.normalize("+news@mail.com").is_err());
+    }
+
+    #[sqlx::test]
+    async fn from_email_finds_accounts_stored_before_the_policy(
+        db_pool: PgPool,
+    ) -> anyhow::Result<()> {
+        for email in ["Jane+news@mail.com", "nope"] {
+            UserRepository::create(&db_pool, email, Some("hash"), Locale::En).await?;
+        }
+        let (mut state, _) = crate::state::test_state::test_state(db_pool);
+        let mut config = (*state.config).clone();
+        config.email_policy.strip_plus_tag = true;
+        state.config = std::sync::Arc::new(config);
+
+        let user = UserService::from_email(&state, " Jane+news@MAIL.com ").await?;
+        assert!(user.is_some_and(|user| user.email == "Jane+news@mail.com"));
+        assert!(UserService::from_email(&state, "nope").await?.is_some());
+        assert!(
+            UserService::from_email(&state, "jane@mail.com")
+                .await?
+                .is_none()
+        );
+
+        // Accounts stored under the policy are found from any tag
+        UserRepository::create(&state.db_pool, "jane@mail.com", Some("hash"), Locale::En).await?;
+        let user = UserService::from_email(&state, "jane+other@mail.com").await?;
+        assert!(user.is_some_and(|user| user.email == "jane@mail.com"));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn email_report_lists_duplicates_and_invalid_addresses(
+        db_pool: PgPool,
+    ) -> anyhow::Result<()> {
+        for email in [
+            "jane+news@mail.com",
+            "Jane@mail.com",
+            "john@mail.com",
+            "nope",
+        ] {
+            UserRepository::create(&db_pool, email, Some("hash"), Locale::En).await?;
+        }
+
+        let report = UserService::email_report(&db_pool, &EmailPolicy::default()).await?;
+        assert!(report.duplicates.is_empty());
+        assert_eq!(report.invalid.len(), 1);
+        assert_eq!(report.invalid[0].0.email, "nope");
+
+        let policy = EmailPolicy {
+            strip_plus_tag: true,
+        };
+        let report = UserService::email_report(&db_pool, &policy).await?;
+        assert_eq!(report.duplicates.len(), 1);
+        assert_eq!(report.duplicates[0].normalized_email, "jane@mail.com");
+        assert_eq!(report.duplicates[0].users.len(), 2);
+
+        Ok(())
+    }
 }
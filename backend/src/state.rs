@@ -0,0 +1,116 @@
+use crate::{
+    config::Config,
+    mailer::{Mailer, mailer_from_config},
+    notifier::{Notifier, notifier_from_config},
+};
+use aws_sdk_s3::Client;
+use axum_macros::FromRef;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// Dependencies shared by every request, built once at startup and injected with `State`
+#[derive(Clone, FromRef)]
+pub struct AppState {
+    pub db_pool: PgPool,
+    pub config: Arc<Config>,
+    pub s3: Client,
+    /// Client for outgoing HTTP calls, like the ones to identity providers
+    pub http: reqwest::Client,
+    pub mailer: Arc<dyn Mailer>,
+    pub notifier: Arc<dyn Notifier>,
+}
+impl AppState {
+    pub fn new(
+        db_pool: PgPool,
+        config: Config,
+        mailer: Arc<dyn Mailer>,
+        notifier: Arc<dyn Notifier>,
+    ) -> Self {
+        Self {
+            db_pool,
+            s3: config.s3.client(),
+            http: reqwest::Client::new(),
+            config: Arc::new(config),
+            mailer,
+            notifier,
+        }
+    }
+
+    /// Mailer and notifier as configured, for the binaries
+    pub fn from_config(db_pool: PgPool, config: Config) -> anyhow::Result<Self> {
+        let mailer = mailer_from_config(&config.mail)?;
+        let notifier = notifier_from_config(&config);
+        Ok(Self::new(db_pool, config, mailer, notifier))
+    }
+}
+
+#[cfg(test)]
+pub mod test_state {
+    use super::*;
+    use crate::{
+        config::{JwtConfig, MailConfig, MailTransport, ReaperConfig, S3Config},
+        mailer::InMemoryMailer,
+        notifier::InMemoryNotifier,
+        services::{EmailPolicy, JwtKey, PasswordPolicy, UploadPolicy},
+    };
+    use chrono::TimeDelta;
+
+    /// Emails and notifications of a test state, each test gets its own
+    pub struct TestOutbox {
+        pub mailbox: Arc<InMemoryMailer>,
+        pub notifications: Arc<InMemoryNotifier>,
+    }
+
+    /// State pointing to the local bucket of `docker compose`, with in-memory mailer and notifier
+    pub fn test_state(db_pool: PgPool) -> (AppState, TestOutbox) {
+        let config = Config {
+            database_url: String::new(),
+            migrations_dir: "migrations".into(),
+            port: 3000,
+            web_host: "http://localhost:8000".to_string(),
+            trusted_proxies: Vec::new(),
+            jwt: JwtConfig {
+                issuer: "http://localhost:8000".to_string(),
+                audience: "fileshare".to_string(),
+                keys: vec![JwtKey::from_secret("test-secret-test-secret-test-secret")],
+                signing_kid: None,
+            },
+            s3: S3Config {
+                url: "http://localhost:9000".to_string(),
+                access_key_id: "minadmin".to_string(),
+                secret_access_key: "minadmin".to_string(),
+                region: "eu-north-1".to_string(),
+                path_style_buckets: true,
+                bucket_name: "usercontent".to_string(),
+            },
+            upload_policy: UploadPolicy::default(),
+            email_policy: EmailPolicy::default(),
+            password_policy: PasswordPolicy::default(),
+            email_templates_dir: None,
+            mail: MailConfig {
+                transport: MailTransport::Memory,
+                from: "noreply@fileshare.test"
+                    .parse()
+                    .expect("Address should be valid"),
+            },
+            reaper: ReaperConfig {
+                interval: None,
+                abandon_after: TimeDelta::days(1),
+                expiry_warning_window: Some(TimeDelta::days(1)),
+            },
+            discord_webhook_url: None,
+            oidc_providers: Vec::new(),
+        };
+        let outbox = TestOutbox {
+            mailbox: Arc::default(),
+            notifications: Arc::default(),
+        };
+        let state = AppState::new(
+            db_pool,
+            config,
+            outbox.mailbox.clone(),
+            outbox.notifications.clone(),
+        );
+        (state, outbox)
+    }
+}
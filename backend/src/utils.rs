@@ -1,53 +1,279 @@
+use crate::config::Config;
 use axum::{
-    http::StatusCode,
+    extract::{
+        ConnectInfo, FromRef, FromRequestParts,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+    },
+    http::{StatusCode, header, request::Parts},
     response::{IntoResponse, Json, Response},
 };
-use axum_macros::FromRequest;
+use axum_macros::{FromRequest, FromRequestParts};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
 use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use uuid::Uuid;
 
 #[derive(FromRequest)]
-#[from_request(via(Json), rejection(ApiMessage))]
+#[from_request(via(Json), rejection(ApiError))]
 pub struct JsonExtract<T>(pub T);
 
-pub struct ApiMessage {
-    pub status: StatusCode,
-    pub message: String,
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct PathExtract<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct QueryExtract<T>(pub T);
+
+/// Address of the client, `None` when the server does not know its peer.
+///
+/// `X-Forwarded-For` is only believed when the peer is one of `Config::trusted_proxies`, anyone
+/// else can write whatever they like in it. Its hops are then walked from the closest one, and the
+/// first address that is not a trusted proxy is the client.
+pub struct ClientIp(pub Option<IpAddr>);
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp
+where
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let config = Arc::<Config>::from_ref(state);
+        let is_trusted = |ip: IpAddr| {
+            config
+                .trusted_proxies
+                .iter()
+                .any(|range| range.contains(ip))
+        };
+        let Ok(ConnectInfo(peer)) =
+            ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await
+        else {
+            return Ok(Self(None));
+        };
+        let mut client = peer.ip().to_canonical();
+        let hops: Vec<&str> = parts
+            .headers
+            .get_all("X-Forwarded-For")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in hops.iter().rev() {
+            if !is_trusted(client) {
+                break;
+            }
+            match hop.trim().parse::<IpAddr>() {
+                Ok(ip) => client = ip.to_canonical(),
+                Err(_) => break,
+            }
+        }
+        Ok(Self(Some(client)))
+    }
+}
+
+/// Error answered by the API, always as a JSON body with a machine-readable `code` and a `message`
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest {
+        code: &'static str,
+        message: String,
+    },
+    Unauthorized {
+        code: &'static str,
+        message: String,
+    },
+    Forbidden {
+        code: &'static str,
+        message: String,
+    },
+    NotFound {
+        code: &'static str,
+        message: String,
+    },
+    Conflict {
+        code: &'static str,
+        message: String,
+    },
+    Gone {
+        code: &'static str,
+        message: String,
+    },
+    Unprocessable {
+        code: &'static str,
+        message: String,
+    },
+    /// Answered with a `Retry-After` header
+    TooManyRequests {
+        code: &'static str,
+        message: String,
+        retry_at: DateTime<Utc>,
+    },
+    /// Problems found in the fields of the request, listed by field
+    Validation {
+        fields: BTreeMap<&'static str, Vec<String>>,
+    },
+    /// The cause is only logged, the client gets an id to find it back with
+    Internal {
+        correlation_id: Uuid,
+    },
+}
+impl ApiError {
+    /// Logs the whole error chain under a new correlation id, none of it reaches the client
+    pub fn internal(err: anyhow::Error) -> Self {
+        let correlation_id = Uuid::new_v4();
+        println!("Internal error {correlation_id}: {err:#}");
+        Self::Internal { correlation_id }
+    }
+
+    /// Validation error of a single field
+    pub fn invalid_field(field: &'static str, problem: &str) -> Self {
+        Self::Validation {
+            fields: BTreeMap::from([(field, vec![problem.to_string()])]),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest { .. } | Self::Validation { .. } => StatusCode::BAD_REQUEST,
+            Self::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            Self::Forbidden { .. } => StatusCode::FORBIDDEN,
+            Self::NotFound { .. } => StatusCode::NOT_FOUND,
+            Self::Conflict { .. } => StatusCode::CONFLICT,
+            Self::Gone { .. } => StatusCode::GONE,
+            Self::Unprocessable { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest { code, .. }
+            | Self::Unauthorized { code, .. }
+            | Self::Forbidden { code, .. }
+            | Self::NotFound { code, .. }
+            | Self::Conflict { code, .. }
+            | Self::Gone { code, .. }
+            | Self::Unprocessable { code, .. }
+            | Self::TooManyRequests { code, .. } => code,
+            Self::Validation { .. } => "validation_failed",
+            Self::Internal { .. } => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::BadRequest { message, .. }
+            | Self::Unauthorized { message, .. }
+            | Self::Forbidden { message, .. }
+            | Self::NotFound { message, .. }
+            | Self::Conflict { message, .. }
+            | Self::Gone { message, .. }
+            | Self::Unprocessable { message, .. }
+            | Self::TooManyRequests { message, .. } => message.clone(),
+            Self::Validation { fields } => fields
+                .iter()
+                .map(|(field, problems)| format!("{field} {}", problems.join(", ")))
+                .collect::<Vec<_>>()
+                .join("; "),
+            Self::Internal { correlation_id } => {
+                format!("Something went wrong on our side, error id {correlation_id}")
+            },
+        }
+    }
+}
+impl From<JsonRejection> for ApiError {
+    fn from(value: JsonRejection) -> Self {
+        Self::BadRequest {
+            code: "invalid_body",
+            message: value.body_text(),
+        }
+    }
 }
-impl ApiMessage {
-    pub fn with_500(message: &str) -> Self {
-        Self {
-            status: StatusCode::INTERNAL_SERVER_ERROR,
-            message: String::from(message),
+impl From<PathRejection> for ApiError {
+    fn from(value: PathRejection) -> Self {
+        Self::BadRequest {
+            code: "invalid_path",
+            message: value.body_text(),
         }
     }
 }
-impl From<StatusCode> for ApiMessage {
-    fn from(value: StatusCode) -> Self {
-        Self {
-            status: value,
-            message: value
-                .canonical_reason()
-                .unwrap_or("Unknown Status Code")
-                .to_string(),
+impl From<QueryRejection> for ApiError {
+    fn from(value: QueryRejection) -> Self {
+        Self::BadRequest {
+            code: "invalid_query",
+            message: value.body_text(),
         }
     }
 }
-impl IntoResponse for ApiMessage {
+impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        (self.status, Json(json!({"message": self.message}))).into_response()
+        let mut body = json!({"code": self.code(), "message": self.message()});
+        match &self {
+            Self::Validation { fields } => body["fields"] = json!(fields),
+            Self::Internal { correlation_id } => body["correlation_id"] = json!(correlation_id),
+            Self::TooManyRequests { retry_at, .. } => {
+                body["retry_at"] = json!(retry_at);
+                let retry_after_secs = (*retry_at - Utc::now()).num_seconds().max(1);
+                return (
+                    self.status(),
+                    [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                    Json(body),
+                )
+                    .into_response();
+            },
+            _ => {},
+        }
+        (self.status(), Json(body)).into_response()
     }
 }
 
-pub fn map_err_to_500<E: ToString>(err: E) -> ApiMessage {
-    ApiMessage {
-        status: StatusCode::INTERNAL_SERVER_ERROR,
-        message: err.to_string(),
-    }
+/// Collects the problems of several fields before answering with a single validation error
+#[derive(Debug, Default)]
+pub struct FieldErrors {
+    pub fields: BTreeMap<&'static str, Vec<String>>,
 }
+impl FieldErrors {
+    pub fn add(&mut self, field: &'static str, problems: Vec<String>) {
+        if !problems.is_empty() {
+            self.fields.entry(field).or_default().extend(problems);
+        }
+    }
 
-pub fn context_to_500(err: anyhow::Error) -> ApiMessage {
-    ApiMessage {
-        status: StatusCode::INTERNAL_SERVER_ERROR,
-        message: format!("{:#}", err),
+    /// `Ok` when no problem was added
+    pub fn into_result(self) -> Result<(), ApiError> {
+        if self.fields.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Validation {
+                fields: self.fields,
+            })
+        }
     }
 }
+
+pub fn map_err_to_500<E: Display>(err: E) -> ApiError {
+    ApiError::internal(anyhow::anyhow!("{err}"))
+}
+
+pub fn context_to_500(err: anyhow::Error) -> ApiError {
+    ApiError::internal(err)
+}
+
+/// Random URL-safe token with 256 bits of entropy
+pub fn random_token() -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// Hash to store high-entropy tokens with, a slow password hash is not needed for them
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -1,9 +1,11 @@
 import { goto } from '$app/navigation';
 import { page } from '$app/state';
-import type { User } from '../types';
+import type { OidcProvider, User } from '../types';
 import { axiosInstance } from './axios';
 
 const localStorageTokenKey = 'FILESHARE_AUTH_TOKEN';
+const localStorageRefreshTokenKey = 'FILESHARE_REFRESH_TOKEN';
+const sessionStorageOidcSecretKey = 'FILESHARE_OIDC_BROWSER_SECRET';
 
 let activeToken: string = $state('');
 let currentUser: User | undefined = $state();
@@ -85,9 +87,87 @@ export function clearToken() {
     activeToken = '';
     try {
         localStorage.removeItem(localStorageTokenKey);
+        localStorage.removeItem(localStorageRefreshTokenKey);
     } catch {}
 }
 
+function setTokens(token: string, refreshToken: string) {
+    setToken(token);
+    try {
+        localStorage.setItem(localStorageRefreshTokenKey, refreshToken);
+    } catch {}
+}
+
+function getRefreshToken(): string | null {
+    try {
+        return localStorage.getItem(localStorageRefreshTokenKey);
+    } catch {
+        return null;
+    }
+}
+
+// Tabs share the tokens, a refresh done by one of them is picked up by the others
+if (typeof window !== 'undefined') {
+    window.addEventListener('storage', (event) => {
+        if (event.key === localStorageTokenKey) {
+            activeToken = event.newValue ?? '';
+        }
+    });
+}
+
+let pendingRefresh: Promise<boolean> | null = null;
+
+/**
+ * Trades the refresh token for new tokens, returns false when the session is over.
+ *
+ * Refresh tokens only work once and replaying one ends the session, so concurrent calls share a
+ * single refresh, and tabs take turns through a lock.
+ */
+export function refreshSession(): Promise<boolean> {
+    if (pendingRefresh === null) {
+        const staleRefreshToken = getRefreshToken();
+        const refresh = () => refreshSessionOnce(staleRefreshToken);
+        const refreshing = navigator.locks ? navigator.locks.request('fileshare-refresh', refresh) : refresh();
+        pendingRefresh = refreshing.finally(() => {
+            pendingRefresh = null;
+        });
+    }
+    return pendingRefresh;
+}
+
+async function refreshSessionOnce(staleRefreshToken: string | null): Promise<boolean> {
+    const refreshToken = getRefreshToken();
+    if (refreshToken === null || refreshToken === '') {
+        return false;
+    }
+    if (refreshToken !== staleRefreshToken) {
+        // Another tab refreshed while this one waited for the lock
+        getToken();
+        return true;
+    }
+    try {
+        const res = await fetch(`${import.meta.env.VITE_API_HOST}/api/users/refresh`, {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({ refresh_token: refreshToken }),
+        });
+        if (!res.ok) {
+            clearToken();
+            currentUser = undefined;
+            return false;
+        }
+        const resBody = await res.json();
+        setTokens(resBody.token, resBody.refresh_token);
+        currentUser = resBody.user;
+        return true;
+    } catch (e) {
+        console.error(e);
+        return false;
+    }
+}
+
 export async function signup(email: string, password: string): Promise<boolean> {
     try {
         const res = await fetch(`${import.meta.env.VITE_API_HOST}/api/users/signup`, {
@@ -102,7 +182,7 @@ export async function signup(email: string, password: string): Promise<boolean>
             alert(resBody.message);
             return false;
         }
-        setToken(resBody.token);
+        setTokens(resBody.token, resBody.refresh_token);
         currentUser = resBody.user;
         return true;
     } catch (e) {
@@ -111,7 +191,10 @@ export async function signup(email: string, password: string): Promise<boolean>
     }
 }
 
-export async function login(email: string, password: string): Promise<boolean> {
+let challengeToken: string = '';
+
+/** Users with 2FA get `'two_factor'`, and finish with `loginTwoFactor` */
+export async function login(email: string, password: string): Promise<'done' | 'two_factor' | 'failed'> {
     try {
         const res = await fetch(`${import.meta.env.VITE_API_HOST}/api/users/login`, {
             method: 'POST',
@@ -121,11 +204,40 @@ export async function login(email: string, password: string): Promise<boolean> {
             body: JSON.stringify({ email, password }),
         });
         const resBody = await res.json();
+        if (!res.ok) {
+            alert(resBody.message);
+            return 'failed';
+        }
+        if (resBody.two_factor_required) {
+            challengeToken = resBody.challenge_token;
+            return 'two_factor';
+        }
+        setTokens(resBody.token, resBody.refresh_token);
+        currentUser = resBody.user;
+        return 'done';
+    } catch (e) {
+        console.error(e);
+        return 'failed';
+    }
+}
+
+/** Second step of the login, with a code of the authenticator app or a recovery code */
+export async function loginTwoFactor(code: string): Promise<boolean> {
+    try {
+        const res = await fetch(`${import.meta.env.VITE_API_HOST}/api/users/login/2fa`, {
+            method: 'POST',
+            headers: {
+                'Content-Type': 'application/json',
+            },
+            body: JSON.stringify({ challenge_token: challengeToken, code }),
+        });
+        const resBody = await res.json();
         if (!res.ok) {
             alert(resBody.message);
             return false;
         }
-        setToken(resBody.token);
+        challengeToken = '';
+        setTokens(resBody.token, resBody.refresh_token);
         currentUser = resBody.user;
         return true;
     } catch (e) {
@@ -134,17 +246,107 @@ export async function login(email: string, password: string): Promise<boolean> {
     }
 }
 
-export function logout(): boolean {
-    setToken('');
+export async function getOidcProviders(): Promise<OidcProvider[]> {
+    try {
+        const res = await axiosInstance.get('/api/oidc/providers');
+        return res.data as OidcProvider[];
+    } catch (e) {
+        console.error(e);
+        return [];
+    }
+}
+
+/** Sends the user to the identity provider, which sends them back to /account/oidc/callback */
+export async function startOidcLogin(providerId: string): Promise<boolean> {
+    try {
+        const res = await axiosInstance.post(`/api/oidc/${providerId}/start`);
+        // Only this tab can finish the login, a callback link from someone else is refused
+        sessionStorage.setItem(sessionStorageOidcSecretKey, res.data.browser_secret);
+        window.location.assign(res.data.authorization_url);
+        return true;
+    } catch (e: any) {
+        alert(e.response?.data?.message ?? 'Failed to reach the identity provider');
+        return false;
+    }
+}
+
+/** Users with 2FA get `'two_factor'`, and finish with `loginTwoFactor` */
+export async function finishOidcLogin(state: string, code: string): Promise<'done' | 'two_factor' | 'failed'> {
+    try {
+        const browserSecret = sessionStorage.getItem(sessionStorageOidcSecretKey) ?? '';
+        sessionStorage.removeItem(sessionStorageOidcSecretKey);
+        const res = await axiosInstance.post('/api/oidc/callback', {
+            state,
+            code,
+            browser_secret: browserSecret,
+        });
+        if (res.data.two_factor_required) {
+            challengeToken = res.data.challenge_token;
+            return 'two_factor';
+        }
+        setTokens(res.data.token, res.data.refresh_token);
+        currentUser = res.data.user as User;
+        return 'done';
+    } catch (e: any) {
+        alert(e.response?.data?.message ?? 'Failed to log in');
+        return 'failed';
+    }
+}
+
+export async function logout(): Promise<boolean> {
+    try {
+        await axiosInstance.post('/api/users/logout');
+    } catch (e) {
+        console.error(e);
+    }
+    clearToken();
+    currentUser = undefined;
+    return true;
+}
+
+export async function logoutEverywhere(): Promise<boolean> {
+    try {
+        await axiosInstance.post('/api/users/logout-everywhere');
+    } catch (e) {
+        console.error(e);
+        return false;
+    }
+    clearToken();
     currentUser = undefined;
     return true;
 }
 
-export async function verify(verificationId: string): Promise<boolean> {
+/** Users with 2FA get `'two_factor'`, and finish logging in with `loginTwoFactor` */
+export async function verify(verificationId: string): Promise<'done' | 'two_factor' | 'failed'> {
     try {
         const res = await axiosInstance.post(`/api/users/verify/${verificationId}`);
-        setToken(res.data.token);
+        if (res.data.two_factor_required) {
+            challengeToken = res.data.challenge_token;
+            return 'two_factor';
+        }
+        setTokens(res.data.token, res.data.refresh_token);
         currentUser = res.data.user as User;
+        return 'done';
+    } catch (e) {
+        console.error(e);
+        return 'failed';
+    }
+}
+
+/** Returns when another email can be asked for, or `null` if sending failed for another reason */
+export async function sendVerification(): Promise<{ sent: boolean; nextResendAt: string | null }> {
+    try {
+        const res = await axiosInstance.post(`/api/users/me/send-verification`);
+        return { sent: true, nextResendAt: res.data.next_resend_at };
+    } catch (e: any) {
+        console.error(e);
+        return { sent: false, nextResendAt: e.response?.data?.retry_at ?? null };
+    }
+}
+
+export async function requestPasswordReset(email: string): Promise<boolean> {
+    try {
+        await axiosInstance.post('/api/users/password-reset', { email });
         return true;
     } catch (e) {
         console.error(e);
@@ -152,9 +354,11 @@ export async function verify(verificationId: string): Promise<boolean> {
     }
 }
 
-export async function sendVerification(): Promise<boolean> {
+export async function resetPassword(token: string, password: string): Promise<boolean> {
     try {
-        const res = await axiosInstance.post(`/api/users/me/send-verification`);
+        await axiosInstance.post(`/api/users/password-reset/${token}`, { password });
+        clearToken();
+        currentUser = undefined;
         return true;
     } catch (e) {
         console.error(e);
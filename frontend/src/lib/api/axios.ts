@@ -1,5 +1,5 @@
 import axios from 'axios';
-import { getToken } from './auth.svelte';
+import { getToken, refreshSession } from './auth.svelte';
 
 export const axiosInstance = axios.create({
     allowAbsoluteUrls: true,
@@ -10,3 +10,13 @@ axiosInstance.interceptors.request.use((config) => {
     config.headers.Authorization = `Bearer ${getToken()}`;
     return config;
 });
+
+// Access tokens are short-lived, get a new one and retry once when it expired
+axiosInstance.interceptors.response.use(undefined, async (error) => {
+    const config = error.config;
+    if (error.response?.status === 401 && config && !config._retried && (await refreshSession())) {
+        config._retried = true;
+        return axiosInstance(config);
+    }
+    return Promise.reject(error);
+});
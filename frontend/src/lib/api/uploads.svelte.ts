@@ -1,4 +1,4 @@
-import type { Upload } from '../types';
+import type { CursorPage, Upload } from '../types';
 import { axiosInstance } from './axios';
 
 let myUploads: Upload[] = $state([]);
@@ -10,21 +10,45 @@ export function lazySetUserUploads(newUserUploads: Upload[]) {
 }
 export async function lazyRefreshUserUploads() {
     try {
-        const res = await axiosInstance.get('/api/uploads/mine');
-        myUploads = res.data as Upload[];
+        const uploads: Upload[] = [];
+        let cursor: string | null = null;
+        do {
+            const res = await axiosInstance.get('/api/uploads/mine', { params: { cursor, limit: 200 } });
+            const page = res.data as CursorPage<Upload>;
+            uploads.push(...page.items);
+            cursor = page.next_cursor;
+        } while (cursor !== null);
+        myUploads = uploads;
     } catch (e) {
         console.error(e);
     }
 }
 
-export async function startNewUpload(file_name: string, content_type: string): Promise<string> {
+export async function startNewUpload(
+    file_name: string,
+    content_type: string,
+    size_bytes: number,
+    password: string | null = null,
+): Promise<{ url: string; upload: Upload }> {
     try {
         const res = await axiosInstance.post('/api/uploads/start', {
             file_name,
             content_type,
+            size_bytes,
+            password,
             expires_at: new Date(Date.now() + 1000 * 60 * 60 * 24),
         });
-        return res.data.url;
+        return res.data;
+    } catch (e) {
+        console.error(e);
+        throw e;
+    }
+}
+
+export async function completeUpload(id: string, size_bytes: number): Promise<Upload> {
+    try {
+        const res = await axiosInstance.post(`/api/uploads/${id}/complete`, { size_bytes });
+        return res.data as Upload;
     } catch (e) {
         console.error(e);
         throw e;
@@ -40,3 +64,13 @@ export async function getUpload(id: string): Promise<Upload | null> {
         return null;
     }
 }
+
+export async function unlockShare(share_token: string, password: string): Promise<string | null> {
+    try {
+        const res = await axiosInstance.post(`/s/${share_token}`, { password });
+        return res.data.url as string;
+    } catch (e) {
+        console.error(e);
+        return null;
+    }
+}
@@ -4,6 +4,16 @@ export interface User {
     updated_at: string;
     email: string;
     verified: boolean;
+    role: 'user' | 'admin';
+    disabled: boolean;
+    locale: 'en' | 'fr';
+    two_factor: boolean;
+    has_password: boolean;
+}
+
+export interface OidcProvider {
+    id: string;
+    name: string;
 }
 
 export interface Upload {
@@ -13,6 +23,31 @@ export interface Upload {
     updated_at: string;
     file_name: string;
     content_type: string;
-    presigned_get: string;
     expires_at: string;
+    status: 'pending' | 'completed' | 'failed' | 'abandoned';
+    size_bytes: number | null;
+    etag: string | null;
+    completed_at: string | null;
+    multipart: boolean;
+    share_token: string;
+    password_protected: boolean;
+    downloads: number;
+    max_downloads: number | null;
+    delete_when_exhausted: boolean;
+}
+
+export type ApiTokenScope = 'uploads:read' | 'uploads:write' | 'uploads:delete';
+
+export interface ApiToken {
+    id: string;
+    created_at: string;
+    name: string;
+    scopes: ApiTokenScope[];
+    expires_at: string | null;
+    last_used_at: string | null;
+}
+
+export interface CursorPage<T> {
+    items: T[];
+    next_cursor: string | null;
 }
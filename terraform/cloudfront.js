@@ -6,8 +6,8 @@
 function handler(event) {
     let req = event.request;
 
-    // don't modify api requests
-    if (req.uri.startsWith("/api")) {
+    // don't modify api requests and share links
+    if (req.uri.startsWith("/api") || req.uri.startsWith("/s/")) {
         return req;
     }
 
@@ -161,6 +161,28 @@ resource "aws_cloudfront_distribution" "clf_distrib" {
     cache_policy_id          = data.aws_cloudfront_cache_policy.clf_apigw_cache_policy_nocache.id
   }
 
+  ordered_cache_behavior {
+    path_pattern           = "/s/*"
+    target_origin_id       = "api-origin"
+    viewer_protocol_policy = "redirect-to-https"
+    allowed_methods        = ["GET", "HEAD", "OPTIONS", "PUT", "POST", "DELETE", "PATCH"]
+    cached_methods         = ["GET", "HEAD", "OPTIONS"]
+
+    origin_request_policy_id = data.aws_cloudfront_origin_request_policy.clf_apigw_origin_request_policy_nohost.id
+    cache_policy_id          = data.aws_cloudfront_cache_policy.clf_apigw_cache_policy_nocache.id
+  }
+
+  ordered_cache_behavior {
+    path_pattern           = "/.well-known/*"
+    target_origin_id       = "api-origin"
+    viewer_protocol_policy = "redirect-to-https"
+    allowed_methods        = ["GET", "HEAD"]
+    cached_methods         = ["GET", "HEAD"]
+
+    origin_request_policy_id = data.aws_cloudfront_origin_request_policy.clf_apigw_origin_request_policy_nohost.id
+    cache_policy_id          = data.aws_cloudfront_cache_policy.clf_apigw_cache_policy_nocache.id
+  }
+
   price_class = "PriceClass_100"
 
   restrictions {
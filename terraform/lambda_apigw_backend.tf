@@ -6,6 +6,30 @@
 ### LAMBDA CONFIGURATION
 ########################################
 
+locals {
+  lambda_backend_environment = {
+    RUST_BACKTRACE = 1
+    RUST_LOG       = "debug"
+    JWT_SECRET     = var.jwt_secret
+    WEB_HOST       = "https://${var.custom_subdomain}.${var.cloudflare_zone_name}"
+
+    DATABASE_URL = "postgres://${var.rds_username}:${var.rds_password}@${aws_db_instance.postgres.address}:${aws_db_instance.postgres.port}/${var.rds_dtbsname}?sslmode=verify-full&sslrootcert=/var/task/certs/eu-north-1-bundle.pem"
+
+    S3_URL                = "https://s3.eu-north-1.amazonaws.com"
+    S3_ACCESS_KEY_ID      = var.s3_access_key_id
+    S3_SECRET_ACCESS_KEY  = var.s3_secret_access_key
+    S3_REGION             = "eu-north-1"
+    S3_PATH_STYLE_BUCKETS = "true"
+    S3_BUCKET_NAME        = var.s3_usercontent_bucket_name
+
+    MAIL_USER     = var.mail_user
+    MAIL_PASSWORD = var.mail_password
+    MAIL_FROM     = var.mail_from
+
+    DISCORD_WEBHOOK_URL = var.discord_webhook_url
+  }
+}
+
 resource "aws_lambda_function" "lambda_backend" {
   function_name    = "fileshare-backend"
   architectures    = ["arm64"]
@@ -27,27 +51,32 @@ resource "aws_lambda_function" "lambda_backend" {
   description = "Backend for ${var.custom_subdomain}.${var.cloudflare_zone_name}"
 
   environment {
-    variables = {
-      RUST_BACKTRACE = 1
-      RUST_LOG       = "debug"
-      JWT_SECRET     = var.jwt_secret
-      WEB_HOST       = "https://${var.custom_subdomain}.${var.cloudflare_zone_name}"
-
-      DATABASE_URL = "postgres://${var.rds_username}:${var.rds_password}@${aws_db_instance.postgres.address}:${aws_db_instance.postgres.port}/${var.rds_dtbsname}?sslmode=verify-full&sslrootcert=/var/task/certs/eu-north-1-bundle.pem"
-
-      S3_URL                = "https://s3.eu-north-1.amazonaws.com"
-      S3_ACCESS_KEY_ID      = var.s3_access_key_id
-      S3_SECRET_ACCESS_KEY  = var.s3_secret_access_key
-      S3_REGION             = "eu-north-1"
-      S3_PATH_STYLE_BUCKETS = "true"
-      S3_BUCKET_NAME        = var.s3_usercontent_bucket_name
-
-      MAIL_USER     = var.mail_user
-      MAIL_PASSWORD = var.mail_password
-      MAIL_FROM     = var.mail_from
-
-      DISCORD_WEBHOOK_URL = var.discord_webhook_url
-    }
+    variables = local.lambda_backend_environment
+  }
+}
+
+resource "aws_lambda_function" "lambda_reaper" {
+  function_name    = "fileshare-reaper"
+  architectures    = ["arm64"]
+  handler          = "reaper" # the bootstrap binary runs the reaper instead of the api with this handler
+  runtime          = "provided.al2023"
+  package_type     = "Zip"
+  role             = aws_iam_role.lambda_exec.arn
+  filename         = "${path.module}/${var.path_to_backend_zip}"
+  source_code_hash = filebase64sha256("${path.module}/${var.path_to_backend_zip}")
+
+  replace_security_groups_on_destroy = true
+
+  vpc_config {
+    subnet_ids         = [aws_subnet.private_1.id, aws_subnet.private_2.id]
+    security_group_ids = [aws_security_group.lambda_sg.id]
+  }
+
+  timeout     = 300
+  description = "Deletes expired uploads for ${var.custom_subdomain}.${var.cloudflare_zone_name}"
+
+  environment {
+    variables = local.lambda_backend_environment
   }
 }
 
@@ -83,6 +112,28 @@ resource "aws_iam_role_policy_attachment" "lambda_iam_vpc_policy" {
   policy_arn = "arn:aws:iam::aws:policy/service-role/AWSLambdaVPCAccessExecutionRole"
 }
 
+########################################
+### SCHEDULE FOR THE REAPER
+########################################
+
+resource "aws_cloudwatch_event_rule" "reaper_schedule" {
+  name                = "fileshare-reaper-schedule"
+  schedule_expression = "rate(1 hour)"
+}
+
+resource "aws_cloudwatch_event_target" "reaper_target" {
+  rule = aws_cloudwatch_event_rule.reaper_schedule.name
+  arn  = aws_lambda_function.lambda_reaper.arn
+}
+
+resource "aws_lambda_permission" "reaper_schedule" {
+  statement_id  = "AllowEventBridgeInvoke"
+  action        = "lambda:InvokeFunction"
+  function_name = aws_lambda_function.lambda_reaper.function_name
+  principal     = "events.amazonaws.com"
+  source_arn    = aws_cloudwatch_event_rule.reaper_schedule.arn
+}
+
 ########################################
 ### APIGATEWAY CONNECTION TO LAMBDA
 ########################################
@@ -111,6 +162,18 @@ resource "aws_apigatewayv2_route" "api_route" {
   target    = "integrations/${aws_apigatewayv2_integration.lambda_integration.id}"
 }
 
+resource "aws_apigatewayv2_route" "share_route" {
+  api_id    = aws_apigatewayv2_api.http_api.id
+  route_key = "ANY /s/{proxy+}"
+  target    = "integrations/${aws_apigatewayv2_integration.lambda_integration.id}"
+}
+
+resource "aws_apigatewayv2_route" "well_known_route" {
+  api_id    = aws_apigatewayv2_api.http_api.id
+  route_key = "GET /.well-known/{proxy+}"
+  target    = "integrations/${aws_apigatewayv2_integration.lambda_integration.id}"
+}
+
 resource "aws_lambda_permission" "apigw" {
   statement_id  = "AllowAPIGatewayInvoke"
   action        = "lambda:InvokeFunction"